@@ -0,0 +1,171 @@
+// Encrypts and decrypts `.age` passwords in-process with the age
+// format. Recipients are X25519 public keys (`age1...`) and secrets
+// come from identity files as written by `age-keygen`.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::crypto::CryptoBackend;
+use crate::enums::{io_error_at, RadomskoError};
+
+pub struct AgeBackend {
+    identity_files: Vec<PathBuf>,
+}
+
+fn crypto_error(err: impl std::fmt::Display) -> RadomskoError {
+    RadomskoError::CryptoError(err.to_string())
+}
+
+impl AgeBackend {
+    // Identity files are only read once something is decrypted, so
+    // that stores without `.age` passwords needn't have any.
+    pub fn new(identity_files: &[PathBuf]) -> AgeBackend {
+        AgeBackend {
+            identity_files: identity_files.to_vec(),
+        }
+    }
+
+    fn identities(&self) -> Result<Vec<Box<dyn age::Identity>>, RadomskoError> {
+        if self.identity_files.is_empty() {
+            return Err(RadomskoError::BadInput(
+                "no age identity files are configured".to_string(),
+            ));
+        }
+        let mut identities = Vec::new();
+        for identity_file in self.identity_files.iter() {
+            let filename = identity_file.to_str().ok_or_else(|| {
+                RadomskoError::BadInput(format!(
+                    "age identity file name is not UTF-8: {}",
+                    identity_file.display()
+                ))
+            })?;
+            let parsed = age::IdentityFile::from_file(filename.to_owned()).map_err(|e| {
+                RadomskoError::IoError(format!("{}: {}", identity_file.display(), e))
+            })?;
+            identities.extend(parsed.into_identities().map_err(crypto_error)?);
+        }
+        Ok(identities)
+    }
+}
+
+impl CryptoBackend for AgeBackend {
+    fn decrypt(&self, password: &Path) -> Result<String, RadomskoError> {
+        let identities = self.identities()?;
+        let encrypted = std::fs::read(password).map_err(io_error_at("read", password))?;
+        let decryptor = age::Decryptor::new_buffered(encrypted.as_slice()).map_err(crypto_error)?;
+        let mut reader = decryptor
+            .decrypt(identities.iter().map(|identity| identity.as_ref()))
+            .map_err(crypto_error)?;
+        let mut cleartext = Vec::new();
+        reader.read_to_end(&mut cleartext).map_err(crypto_error)?;
+        String::from_utf8(cleartext)
+            .map_err(|_| RadomskoError::BadInput("password is not UTF-8".to_string()))
+    }
+
+    fn encrypt(&self, cleartext: &str, recipients: &[String]) -> Result<Vec<u8>, RadomskoError> {
+        assert!(!recipients.is_empty());
+        let recipients = recipients
+            .iter()
+            .map(|recipient| {
+                age::x25519::Recipient::from_str(recipient)
+                    .map_err(|e| RadomskoError::InvalidRecipient(recipient.clone(), e.to_string()))
+            })
+            .collect::<Result<Vec<_>, RadomskoError>>()?;
+        let encryptor = age::Encryptor::with_recipients(
+            recipients
+                .iter()
+                .map(|recipient| recipient as &dyn age::Recipient),
+        )
+        .map_err(crypto_error)?;
+
+        let mut encrypted = Vec::new();
+        let mut writer = encryptor
+            .wrap_output(&mut encrypted)
+            .map_err(crypto_error)?;
+        writer
+            .write_all(cleartext.as_bytes())
+            .map_err(crypto_error)?;
+        writer.finish().map_err(crypto_error)?;
+        Ok(encrypted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    // Writes a throwaway identity into `directory` and returns its path
+    // along with its recipient.
+    fn scratch_identity(directory: &Path, name: &str) -> (PathBuf, String) {
+        let identity = age::x25519::Identity::generate();
+        let path = directory.join(name);
+        std::fs::write(&path, format!("{}\n", identity.to_string().expose_secret())).unwrap();
+        (path, identity.to_public().to_string())
+    }
+
+    #[test]
+    fn round_trip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (identity_file, recipient) = scratch_identity(tmp_dir.path(), "alice");
+        let backend = AgeBackend::new(&[identity_file]);
+        let password = tmp_dir.path().join("password.age");
+        std::fs::write(
+            &password,
+            backend
+                .encrypt("hunter2\nusername: alice\n", &[recipient])
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            backend.decrypt(&password).unwrap(),
+            "hunter2\nusername: alice\n"
+        );
+    }
+
+    #[test]
+    fn decrypt_needs_matching_identity() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (_, alice) = scratch_identity(tmp_dir.path(), "alice");
+        let (bob_identity_file, _) = scratch_identity(tmp_dir.path(), "bob");
+        let backend = AgeBackend::new(&[bob_identity_file]);
+        let password = tmp_dir.path().join("password.age");
+        std::fs::write(&password, backend.encrypt("hunter2", &[alice]).unwrap()).unwrap();
+        let err = backend.decrypt(&password).unwrap_err();
+        assert!(matches!(err, RadomskoError::CryptoError { .. }));
+    }
+
+    #[test]
+    fn decrypt_needs_identity_files() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let password = tmp_dir.path().join("password.age");
+        std::fs::write(&password, "").unwrap();
+        let err = AgeBackend::new(&[]).decrypt(&password).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn decrypt_rejects_non_utf8_identity_file_name() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let password = tmp_dir.path().join("password.age");
+        std::fs::write(&password, "").unwrap();
+        let identity_file = tmp_dir
+            .path()
+            .join(std::ffi::OsStr::from_bytes(b"identity-\xff"));
+        let err = AgeBackend::new(&[identity_file])
+            .decrypt(&password)
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn encrypt_rejects_bad_recipient() {
+        let err = AgeBackend::new(&[])
+            .encrypt("hunter2", &["alice@example.com".to_string()])
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::InvalidRecipient { .. }));
+    }
+}
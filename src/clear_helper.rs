@@ -0,0 +1,349 @@
+// Clears the clipboard from a detached background process so that
+// radomsko can return to the shell right after copying.
+//
+// The helper holds an exclusive lock on its pid file for as long as it
+// lives, which tells a later invocation whether the pid in it is still
+// a pending clear (and not some unrelated, reused pid).
+
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::clipboard::Clipboard;
+use crate::enums::{io_error, io_error_at, RadomskoError};
+use crate::hooks::{HookEvent, Hooks};
+
+const PID_FILE: &str = "clipboard-clear.pid";
+// Where the helper writes what it would otherwise have said on stderr,
+// alongside its pid file.
+const LOG_FILE: &str = "clipboard-clear.log";
+const LOG_FILE_PERMISSIONS: u32 = 0o600;
+const RUNTIME_DIRECTORY_PERMISSIONS: u32 = 0o700;
+
+// How long to wait for a pending clear to finish after asking it to.
+const CANCEL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+const CANCEL_POLL_ATTEMPTS: usize = 100;
+
+// Returns where the pid file of the helper lives, creating its
+// directory if need be: `$XDG_RUNTIME_DIR/radomsko`, falling back on a
+// per-user directory under the system temporary directory.
+pub fn pid_file() -> Result<PathBuf, RadomskoError> {
+    let directory = match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(runtime_dir) if !runtime_dir.is_empty() => PathBuf::from(runtime_dir).join("radomsko"),
+        _ => {
+            // SAFETY: `getuid()` always succeeds.
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("radomsko-{}", uid))
+        }
+    };
+    create_runtime_directory(&directory)?;
+    Ok(directory.join(PID_FILE))
+}
+
+// Creates `directory` if need be and makes sure that it is ours alone.
+// Under a shared temporary directory, another user could have created
+// it first to plant a pid file (and have us signal any process) or a
+// symlink in place of the log.
+fn create_runtime_directory(directory: &Path) -> Result<(), RadomskoError> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(RUNTIME_DIRECTORY_PERMISSIONS)
+        .create(directory)
+        .map_err(io_error_at("create", directory))?;
+    let metadata = std::fs::symlink_metadata(directory).map_err(io_error_at("stat", directory))?;
+    // SAFETY: `getuid()` always succeeds.
+    let uid = unsafe { libc::getuid() };
+    if !metadata.is_dir()
+        || metadata.uid() != uid
+        || metadata.permissions().mode() & 0o777 != RUNTIME_DIRECTORY_PERMISSIONS
+    {
+        return Err(RadomskoError::BadPermissions(directory.to_path_buf()));
+    }
+    Ok(())
+}
+
+// Tries to take the lock on `file`, opened from `path`, without
+// blocking. Returns whether it was taken.
+fn try_lock(file: &std::fs::File, path: &Path) -> Result<bool, RadomskoError> {
+    // SAFETY: `file` owns a valid descriptor for the duration.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(io_error_at("lock", path)(err)),
+    }
+}
+
+// Makes a pending clear (if any) act now, so that a new secret isn't
+// wiped by the timer of an older one, and waits for it to finish.
+pub fn cancel_pending_clear(pid_file: &Path) -> Result<(), RadomskoError> {
+    let mut file = match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(pid_file)
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(io_error_at("open", pid_file)(e)),
+    };
+    if try_lock(&file, pid_file)? {
+        return Ok(());
+    }
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(io_error_at("read", pid_file))?;
+    let pid: libc::pid_t = contents
+        .trim()
+        .parse()
+        .map_err(|_| RadomskoError::BadInput(format!("bad pid file {}", pid_file.display())))?;
+    // SAFETY: `kill()` has no memory safety requirements.
+    unsafe {
+        libc::kill(pid, libc::SIGTERM);
+    }
+    for _ in 0..CANCEL_POLL_ATTEMPTS {
+        if try_lock(&file, pid_file)? {
+            return Ok(());
+        }
+        std::thread::sleep(CANCEL_POLL_INTERVAL);
+    }
+    Err(RadomskoError::SubprocessError(format!(
+        "pending clipboard clear (pid {}) did not finish",
+        pid
+    )))
+}
+
+// What the helper needs to take the secret of `entry` back off the
+// clipboard.
+#[derive(Clone)]
+pub struct PendingClear {
+    pub entry: String,
+    pub clipboard: Clipboard,
+    pub secret: String,
+    // The contents of the clipboard before the secret was copied.
+    pub previous: Option<String>,
+    pub hooks: Hooks,
+}
+
+impl PendingClear {
+    fn finish(&self) -> Result<(), RadomskoError> {
+        self.clipboard
+            .restore(self.secret.as_str(), self.previous.as_deref())?;
+        self.hooks
+            .run_and_report(HookEvent::Cleared, self.entry.as_str());
+        Ok(())
+    }
+}
+
+// Runs in the detached helper: records its pid, then restores the
+// clipboard once `timeout` runs out or a signal arrives.
+fn run_helper(pid_file: &Path, pending: PendingClear, timeout: u64) -> Result<(), RadomskoError> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(pid_file)
+        .map_err(io_error_at("open", pid_file))?;
+    // SAFETY: `file` owns a valid descriptor for the duration.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io_error_at("lock", pid_file)(
+            std::io::Error::last_os_error(),
+        ));
+    }
+    file.set_len(0)
+        .and_then(|_| file.rewind())
+        .and_then(|_| write!(file, "{}", std::process::id()))
+        .and_then(|_| file.sync_data())
+        .map_err(io_error_at("write", pid_file))?;
+
+    // SIGINT, SIGTERM and SIGHUP all restore the clipboard early.
+    let interrupted = pending.clone();
+    ctrlc::set_handler(move || {
+        let status = match interrupted.finish() {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                1
+            }
+        };
+        std::process::exit(status);
+    })
+    .map_err(|e| RadomskoError::SubprocessError(e.to_string()))?;
+
+    std::thread::sleep(std::time::Duration::from_secs(timeout));
+    pending.finish()
+}
+
+// Points stdin and stdout at /dev/null and stderr at `log_file` so that
+// the helper holds on to neither the terminal nor a pipe its caller is
+// reading (as in `$(radomsko show -c ... 2>&1)`). Failing hooks and
+// restores are thus still reported, in `log_file`, or nowhere if it
+// cannot be opened.
+fn detach_standard_streams(log_file: &Path) -> Result<(), RadomskoError> {
+    let null = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(io_error_at("open", Path::new("/dev/null")))?;
+    let log = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(LOG_FILE_PERMISSIONS)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(log_file);
+    let stderr = match &log {
+        Ok(log) => log.as_raw_fd(),
+        Err(_) => null.as_raw_fd(),
+    };
+    for (from, to) in [
+        (null.as_raw_fd(), libc::STDIN_FILENO),
+        (null.as_raw_fd(), libc::STDOUT_FILENO),
+        (stderr, libc::STDERR_FILENO),
+    ] {
+        // SAFETY: all descriptors are valid.
+        if unsafe { libc::dup2(from, to) } < 0 {
+            return Err(io_error("redirect the standard streams")(
+                std::io::Error::last_os_error(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Starts a helper, detached from the calling session, that finishes
+// `pending` after `timeout` seconds. Returns as soon as the helper is
+// running.
+pub fn spawn_clear(
+    pid_file: &Path,
+    pending: PendingClear,
+    timeout: u64,
+) -> Result<(), RadomskoError> {
+    // Flushes now so that buffered output isn't written twice.
+    std::io::stdout()
+        .flush()
+        .and_then(|_| std::io::stderr().flush())
+        .map_err(io_error("flush the standard streams"))?;
+
+    // SAFETY: radomsko is single-threaded at this point, so the child
+    // is free to carry on as normal.
+    match unsafe { libc::fork() } {
+        -1 => Err(io_error("start the clipboard clearing helper")(
+            std::io::Error::last_os_error(),
+        )),
+        0 => {
+            // SAFETY: these calls have no memory safety requirements.
+            unsafe {
+                libc::setsid();
+                // Forks again so that the helper, not being a session
+                // leader, can never acquire a controlling terminal.
+                if libc::fork() != 0 {
+                    libc::_exit(0);
+                }
+            }
+            let _ = std::env::set_current_dir("/");
+            let status = match detach_standard_streams(&pid_file.with_file_name(LOG_FILE))
+                .and_then(|_| run_helper(pid_file, pending, timeout))
+            {
+                Ok(_) => 0,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    1
+                }
+            };
+            std::process::exit(status);
+        }
+        child => {
+            // SAFETY: `child` is our own child process.
+            if unsafe { libc::waitpid(child, std::ptr::null_mut(), 0) } < 0 {
+                return Err(io_error("wait for the clipboard clearing helper")(
+                    std::io::Error::last_os_error(),
+                ));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_without_pid_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        cancel_pending_clear(&tmp_dir.path().join(PID_FILE)).unwrap();
+    }
+
+    #[test]
+    fn cancel_ignores_stale_pid_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let pid_file = tmp_dir.path().join(PID_FILE);
+        // Nobody holds the lock, so this pid must not be signaled.
+        std::fs::write(&pid_file, "1").unwrap();
+        cancel_pending_clear(&pid_file).unwrap();
+    }
+
+    #[test]
+    fn runtime_directory_must_be_private() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let directory = tmp_dir.path().join("radomsko");
+        create_runtime_directory(&directory).unwrap();
+        create_runtime_directory(&directory).unwrap();
+
+        std::fs::set_permissions(&directory, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            create_runtime_directory(&directory).unwrap_err(),
+            RadomskoError::BadPermissions(directory.clone())
+        );
+
+        let link = tmp_dir.path().join("link");
+        std::fs::set_permissions(&directory, std::fs::Permissions::from_mode(0o700)).unwrap();
+        std::os::unix::fs::symlink(&directory, &link).unwrap();
+        assert_eq!(
+            create_runtime_directory(&link).unwrap_err(),
+            RadomskoError::BadPermissions(link)
+        );
+    }
+
+    #[test]
+    fn cancel_refuses_symlinked_pid_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let pid_file = tmp_dir.path().join(PID_FILE);
+        let planted = tmp_dir.path().join("planted");
+        std::fs::write(&planted, "1").unwrap();
+        std::os::unix::fs::symlink(&planted, &pid_file).unwrap();
+        let err = cancel_pending_clear(&pid_file).unwrap_err();
+        assert!(matches!(err, RadomskoError::IoError { .. }));
+    }
+
+    #[test]
+    fn cancel_signals_pending_clear() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let pid_file = tmp_dir.path().join(PID_FILE);
+        let ready = tmp_dir.path().join("ready");
+        // Stands in for a helper by locking the pid file and writing
+        // its pid into it before sleeping.
+        let mut helper = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!(
+                "exec 9>>'{pid_file}'; flock 9; echo $$ > '{pid_file}'; touch '{ready}'; exec sleep 30",
+                pid_file = pid_file.display(),
+                ready = ready.display()
+            ))
+            .spawn()
+            .unwrap();
+        while !ready.exists() {
+            std::thread::sleep(CANCEL_POLL_INTERVAL);
+        }
+
+        cancel_pending_clear(&pid_file).unwrap();
+        let status = helper.wait().unwrap();
+        assert!(!status.success());
+    }
+}
@@ -1,10 +1,10 @@
 use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
-use crate::enums::RadomskoError;
+use crate::enums::{io_error_at, RadomskoError};
 
 const CLEARTEXT_DIRECTORY_REQUIRED_PERMISSIONS: u32 = 0o700;
-const CLEARTEXT_TEMPFILE_PREFIX: &'static str = "radomsko-cleartext-";
+const CLEARTEXT_TEMPFILE_PREFIX: &str = "radomsko-cleartext-";
 
 // Interacts with the quasi-private space that holds cleartext
 // passwords.
@@ -18,8 +18,12 @@ pub struct CleartextHolderInterface {
 }
 
 fn default_cleartext_holder_dir() -> Result<PathBuf, RadomskoError> {
-    let xdg_runtime_dir = std::env::var("XDG_RUNTIME_DIR")?;
-    Ok(PathBuf::from(xdg_runtime_dir.as_str()))
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(xdg_runtime_dir) => Ok(PathBuf::from(xdg_runtime_dir.as_str())),
+        Err(_) => Err(RadomskoError::BadInput(
+            "XDG_RUNTIME_DIR is unset and no cleartext_dir is configured".to_string(),
+        )),
+    }
 }
 
 impl CleartextHolderInterface {
@@ -29,38 +33,26 @@ impl CleartextHolderInterface {
             false => PathBuf::from(configured_root),
         };
 
-        let metadata = std::fs::metadata(root.as_path())?;
+        let metadata = std::fs::metadata(root.as_path())
+            .map_err(io_error_at("use cleartext directory", &root))?;
         if !metadata.is_dir() {
-            return Err(RadomskoError::NotFound);
+            return Err(RadomskoError::IoError(format!(
+                "cleartext directory {} is not a directory",
+                root.display()
+            )));
         } else if metadata.permissions().mode() & 0o777 != CLEARTEXT_DIRECTORY_REQUIRED_PERMISSIONS
         {
-            return Err(RadomskoError::BadPermissions);
+            return Err(RadomskoError::BadPermissions(root));
         }
 
-        Ok(CleartextHolderInterface { root: root })
+        Ok(CleartextHolderInterface { root })
     }
 
     pub fn new_entry(&self) -> Result<tempfile::NamedTempFile, RadomskoError> {
-        Ok(tempfile::Builder::new()
+        tempfile::Builder::new()
             .prefix(CLEARTEXT_TEMPFILE_PREFIX)
-            .tempfile_in(&self.root)?)
-    }
-
-    // `target` names a file that we have asked gpg to encrypt.
-    // Returns the contents of the encrypted output.
-    pub fn encrypted_contents_for(target: &Path) -> Result<Vec<u8>, RadomskoError> {
-        let mut encrypted_target = target.to_path_buf();
-        encrypted_target.set_extension("gpg");
-        Ok(std::fs::read(encrypted_target)?)
-    }
-
-    pub fn remove_encrypted_output_of(&self, target: &Path) -> Result<(), RadomskoError> {
-        assert!(target.starts_with(&self.root));
-
-        let mut encrypted_target = target.to_path_buf();
-        encrypted_target.set_extension("gpg");
-
-        Ok(std::fs::remove_file(encrypted_target)?)
+            .tempfile_in(&self.root)
+            .map_err(io_error_at("create a tempfile in", &self.root))
     }
 }
 
@@ -69,7 +61,7 @@ mod tests {
     use super::*;
 
     const CLEARTEXT_DIRECTORY_BAD_PERMISSIONS: u32 = 0o740;
-    const CLEARTEXT_DIRECTORY_PREFIX: &'static str = "cleartext-holder-fixture-";
+    const CLEARTEXT_DIRECTORY_PREFIX: &str = "cleartext-holder-fixture-";
 
     fn test_data_path(path: &str) -> PathBuf {
         let mut result = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -91,8 +83,8 @@ mod tests {
             backing_dir: tempfile::TempDir,
         ) -> CleartextHolderFixture {
             CleartextHolderFixture {
-                interface: interface,
-                backing_dir: backing_dir,
+                interface,
+                backing_dir,
             }
         }
     }
@@ -131,7 +123,10 @@ mod tests {
         permissions.set_mode(CLEARTEXT_DIRECTORY_BAD_PERMISSIONS);
 
         let err = CleartextHolderInterface::new(tmp_dir.as_ref().to_str().unwrap()).unwrap_err();
-        assert_eq!(err, RadomskoError::BadPermissions);
+        assert_eq!(
+            err,
+            RadomskoError::BadPermissions(tmp_dir.path().to_path_buf())
+        );
     }
 
     #[test]
@@ -171,17 +166,4 @@ mod tests {
         assert!(temporary.close().is_ok());
         assert!(!temporary_path.exists());
     }
-
-    #[test]
-    fn encrypted_contents_for_expects_gpg_file() {
-        let fixture = cleartext_holder_fixture();
-        let temporary = fixture.interface.new_entry().unwrap();
-
-        // We expect that `new_entry()` spawns a tempfile.
-        assert!(temporary.path().is_file());
-
-        // But we've spawned nothing else in the backing directory,
-        // so we expect `encrypted_contents_for()` to fail.
-        assert!(CleartextHolderInterface::encrypted_contents_for(temporary.path()).is_err());
-    }
 }
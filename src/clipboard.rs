@@ -0,0 +1,592 @@
+// Puts text on (and takes it off of) the clipboard through whichever
+// mechanism suits the session radomsko runs in.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use subprocess::Exec;
+
+use crate::enums::{io_error_at, popen_error_for, RadomskoError};
+use crate::external_commands::return_exit_status;
+
+// The tmux buffer that secrets go in, so that clearing one never takes
+// any other buffer with it.
+const TMUX_BUFFER: &str = "radomsko";
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ClipboardBackend {
+    WlCopy,
+    Xclip,
+    Xsel,
+    Tmux,
+    // Asks the terminal to set the clipboard with an OSC 52 escape
+    // sequence, which works across SSH.
+    Osc52,
+}
+
+// Returns whether an executable named `program` is in one of the
+// directories listed in `path_var`.
+fn is_on_path(program: &str, path_var: &str) -> bool {
+    std::env::split_paths(path_var).any(|directory| directory.join(program).is_file())
+}
+
+// Returns the OSC 52 escape sequence setting the clipboard to `text`.
+// Empty `text` clears the clipboard.
+fn osc52_sequence(text: &str) -> String {
+    format!(
+        "\x1b]52;c;{}\x07",
+        data_encoding::BASE64.encode(text.as_bytes())
+    )
+}
+
+impl ClipboardBackend {
+    // Parses a backend named in the configuration. Returns `None` for
+    // "auto," i.e. to detect the backend.
+    pub fn from_name(name: &str) -> Result<Option<ClipboardBackend>, RadomskoError> {
+        match name {
+            "auto" => Ok(None),
+            "wl-copy" => Ok(Some(ClipboardBackend::WlCopy)),
+            "xclip" => Ok(Some(ClipboardBackend::Xclip)),
+            "xsel" => Ok(Some(ClipboardBackend::Xsel)),
+            "tmux" => Ok(Some(ClipboardBackend::Tmux)),
+            "osc52" => Ok(Some(ClipboardBackend::Osc52)),
+            _ => Err(RadomskoError::BadInput(format!(
+                "unknown clipboard backend ``{}''",
+                name
+            ))),
+        }
+    }
+
+    // Picks a backend for the session described by `env`.
+    //
+    // Remote sessions use tmux if inside it and OSC 52 otherwise, as
+    // any display they see is likely not the user's. Local sessions
+    // prefer Wayland, then X11 (xclip over xsel), then tmux.
+    pub fn detect(env: &dyn Fn(&str) -> Option<String>) -> ClipboardBackend {
+        let is_set = |name: &str| env(name).is_some_and(|value| !value.is_empty());
+        if is_set("SSH_TTY") {
+            return match is_set("TMUX") {
+                true => ClipboardBackend::Tmux,
+                false => ClipboardBackend::Osc52,
+            };
+        }
+        if is_set("WAYLAND_DISPLAY") {
+            ClipboardBackend::WlCopy
+        } else if is_set("DISPLAY") {
+            let path_var = env("PATH").unwrap_or_default();
+            match !is_on_path("xclip", &path_var) && is_on_path("xsel", &path_var) {
+                true => ClipboardBackend::Xsel,
+                false => ClipboardBackend::Xclip,
+            }
+        } else if is_set("TMUX") {
+            ClipboardBackend::Tmux
+        } else {
+            ClipboardBackend::Osc52
+        }
+    }
+
+    fn default_program(&self) -> &'static str {
+        match self {
+            ClipboardBackend::WlCopy => "wl-copy",
+            ClipboardBackend::Xclip => "xclip",
+            ClipboardBackend::Xsel => "xsel",
+            ClipboardBackend::Tmux => "tmux",
+            ClipboardBackend::Osc52 => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    backend: ClipboardBackend,
+    program: String,
+    // Has `wl-copy` serve only a single paste.
+    paste_once: bool,
+    // Has `wl-copy` and `xclip` also offer the secret under the
+    // `x-kde-passwordManagerHint` MIME type, which tells clipboard
+    // managers (Klipper, GPaste, cliphist, ...) not to record it. Needs
+    // a `wl-copy` or `xclip` recent enough to know `--sensitive`.
+    sensitive: bool,
+}
+
+impl Clipboard {
+    // Uses `backend`, or detects one from the environment if `None`.
+    // `program` replaces the binary the backend would otherwise run.
+    pub fn new(
+        backend: Option<ClipboardBackend>,
+        program: Option<&str>,
+        paste_once: bool,
+        sensitive: bool,
+    ) -> Clipboard {
+        let backend =
+            backend.unwrap_or_else(|| ClipboardBackend::detect(&|name| std::env::var(name).ok()));
+        Clipboard {
+            backend,
+            program: program.unwrap_or(backend.default_program()).to_owned(),
+            paste_once,
+            sensitive,
+        }
+    }
+
+    // Returns `wl-paste` from alongside `wl-copy` if the latter was
+    // given with a directory.
+    fn wl_paste_program(&self) -> PathBuf {
+        match Path::new(&self.program).parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory.join("wl-paste"),
+            _ => PathBuf::from("wl-paste"),
+        }
+    }
+
+    fn command(&self) -> Exec {
+        Exec::cmd(self.program.as_str())
+            .stdout(subprocess::NullFile)
+            .stderr(subprocess::NullFile)
+    }
+
+    fn write_to_terminal(&self, sequence: &str) -> Result<(), RadomskoError> {
+        let tty_path = Path::new("/dev/tty");
+        let mut tty = std::fs::OpenOptions::new()
+            .write(true)
+            .open(tty_path)
+            .map_err(io_error_at("open", tty_path))?;
+        tty.write_all(sequence.as_bytes())
+            .and_then(|_| tty.flush())
+            .map_err(io_error_at("write to", tty_path))
+    }
+
+    // Feeds `input` to `command` (one of `self.program`) and fails
+    // unless it succeeds, saying what it was meant to `do`.
+    fn run(&self, command: Exec, input: &str, action: &str) -> Result<(), RadomskoError> {
+        let capture_data = command
+            .stdin(input)
+            .capture()
+            .map_err(popen_error_for(self.program.as_str()))?;
+        return_exit_status(capture_data.exit_status)
+            .map_err(|e| e.context(format!("cannot {} with {}", action, self.program)))
+    }
+
+    pub fn copy(&self, text: &str) -> Result<(), RadomskoError> {
+        let command = match self.backend {
+            ClipboardBackend::WlCopy => {
+                let mut command = self.command();
+                if self.paste_once {
+                    command = command.arg("--paste-once");
+                }
+                if self.sensitive {
+                    command = command.arg("--sensitive");
+                }
+                command
+            }
+            ClipboardBackend::Xclip => {
+                let command = self.command().arg("-selection").arg("clipboard");
+                match self.sensitive {
+                    true => command.arg("-sensitive"),
+                    false => command,
+                }
+            }
+            ClipboardBackend::Xsel => self.command().arg("--clipboard").arg("--input"),
+            ClipboardBackend::Tmux => self
+                .command()
+                .arg("load-buffer")
+                .arg("-b")
+                .arg(TMUX_BUFFER)
+                .arg("-w")
+                .arg("-"),
+            ClipboardBackend::Osc52 => return self.write_to_terminal(&osc52_sequence(text)),
+        };
+        self.run(command, text, "copy to the clipboard")
+    }
+
+    pub fn clear(&self) -> Result<(), RadomskoError> {
+        let command = match self.backend {
+            ClipboardBackend::WlCopy => self.command().arg("-c"),
+            // xclip has no way to clear the clipboard, so this takes
+            // it over with nothing instead.
+            ClipboardBackend::Xclip => self.command().arg("-selection").arg("clipboard"),
+            ClipboardBackend::Xsel => self.command().arg("--clipboard").arg("--clear"),
+            // `copy()` also set the clipboard of the outer terminal,
+            // which deleting the buffer leaves alone. tmux ignores
+            // empty data, so a lone space overwrites both before the
+            // buffer goes.
+            ClipboardBackend::Tmux => {
+                let command = self
+                    .command()
+                    .arg("load-buffer")
+                    .arg("-b")
+                    .arg(TMUX_BUFFER)
+                    .arg("-w")
+                    .arg("-")
+                    .arg(";")
+                    .arg("delete-buffer")
+                    .arg("-b")
+                    .arg(TMUX_BUFFER);
+                return self.run(command, " ", "clear the clipboard");
+            }
+            ClipboardBackend::Osc52 => return self.write_to_terminal(&osc52_sequence("")),
+        };
+        self.run(command, "", "clear the clipboard")
+    }
+
+    // Returns the current contents of the clipboard as text, or `None`
+    // if it is empty or holds text that isn't UTF-8. Fails if its
+    // backend cannot read it, which some also do for an empty clipboard
+    // or one holding something other than text.
+    //
+    // Only text is asked for, as anything else (an image, say) could
+    // not be put back faithfully by `copy()`.
+    pub fn paste(&self) -> Result<Option<String>, RadomskoError> {
+        let program = match self.backend {
+            ClipboardBackend::WlCopy => self.wl_paste_program(),
+            _ => PathBuf::from(&self.program),
+        };
+        let command = Exec::cmd(&program);
+        let command = match self.backend {
+            ClipboardBackend::WlCopy => command.arg("--type").arg("text/plain").arg("--no-newline"),
+            ClipboardBackend::Xclip => command
+                .arg("-selection")
+                .arg("clipboard")
+                .arg("-t")
+                .arg("UTF8_STRING")
+                .arg("-o"),
+            ClipboardBackend::Xsel => command.arg("--clipboard").arg("--output"),
+            ClipboardBackend::Tmux => command.arg("save-buffer").arg("-"),
+            ClipboardBackend::Osc52 => return Ok(None),
+        };
+        let capture_data = command
+            .stdin(subprocess::NullFile)
+            .stdout(subprocess::Redirection::Pipe)
+            .stderr(subprocess::NullFile)
+            .capture()
+            .map_err(popen_error_for(&program.to_string_lossy()))?;
+        return_exit_status(capture_data.exit_status).map_err(|e| {
+            e.context(format!(
+                "cannot read the clipboard with {}",
+                program.display()
+            ))
+        })?;
+        match capture_data.stdout.is_empty() {
+            true => Ok(None),
+            false => Ok(String::from_utf8(capture_data.stdout).ok()),
+        }
+    }
+
+    // Takes `secret` back off the clipboard, putting back `previous` if
+    // given. Leaves the clipboard alone if `secret` has since been
+    // replaced.
+    pub fn restore(&self, secret: &str, previous: Option<&str>) -> Result<(), RadomskoError> {
+        // Without a way to check, the secret must be assumed present.
+        if self.backend != ClipboardBackend::Osc52 {
+            match self.paste() {
+                Ok(current) if current.as_deref() != Some(secret) => return Ok(()),
+                Ok(_) => (),
+                Err(e) => eprintln!("Warning: {}", e),
+            }
+        }
+        match previous {
+            Some(previous) => self.copy(previous),
+            None => self.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn fake_env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    // Creates an executable `name` in `directory` that records its
+    // arguments and stdin in `name.log`.
+    fn stub_binary(directory: &Path, name: &str) -> String {
+        let path = directory.join(name);
+        let log = directory.join(format!("{}.log", name));
+        std::fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> '{}'\ncat >> '{}'\n",
+                log.display(),
+                log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn stub_log(directory: &Path, name: &str) -> String {
+        std::fs::read_to_string(directory.join(format!("{}.log", name))).unwrap()
+    }
+
+    #[test]
+    fn from_name() {
+        assert_eq!(ClipboardBackend::from_name("auto").unwrap(), None);
+        assert_eq!(
+            ClipboardBackend::from_name("xsel").unwrap(),
+            Some(ClipboardBackend::Xsel)
+        );
+        let err = ClipboardBackend::from_name("pbcopy").unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn detect_local_sessions() {
+        for (vars, expected) in [
+            (
+                vec![("WAYLAND_DISPLAY", "wayland-1"), ("DISPLAY", ":0")],
+                ClipboardBackend::WlCopy,
+            ),
+            (vec![("DISPLAY", ":0")], ClipboardBackend::Xclip),
+            (
+                vec![("TMUX", "/tmp/tmux-1000/default")],
+                ClipboardBackend::Tmux,
+            ),
+            (vec![("WAYLAND_DISPLAY", "")], ClipboardBackend::Osc52),
+            (vec![], ClipboardBackend::Osc52),
+        ] {
+            assert_eq!(ClipboardBackend::detect(&fake_env(&vars)), expected);
+        }
+    }
+
+    #[test]
+    fn detect_remote_sessions() {
+        let env = fake_env(&[("SSH_TTY", "/dev/pts/3"), ("DISPLAY", "localhost:10")]);
+        assert_eq!(ClipboardBackend::detect(&env), ClipboardBackend::Osc52);
+        let env = fake_env(&[
+            ("SSH_TTY", "/dev/pts/3"),
+            ("TMUX", "/tmp/tmux-1000/default"),
+        ]);
+        assert_eq!(ClipboardBackend::detect(&env), ClipboardBackend::Tmux);
+    }
+
+    #[test]
+    fn detect_prefers_xclip_over_xsel() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path_var = tmp_dir.path().to_str().unwrap();
+        stub_binary(tmp_dir.path(), "xsel");
+        let env = fake_env(&[("DISPLAY", ":0"), ("PATH", path_var)]);
+        assert_eq!(ClipboardBackend::detect(&env), ClipboardBackend::Xsel);
+
+        stub_binary(tmp_dir.path(), "xclip");
+        assert_eq!(ClipboardBackend::detect(&env), ClipboardBackend::Xclip);
+    }
+
+    #[test]
+    fn osc52_sequence_encodes_text() {
+        assert_eq!(osc52_sequence("hunter2"), "\x1b]52;c;aHVudGVyMg==\x07");
+        assert_eq!(osc52_sequence(""), "\x1b]52;c;\x07");
+    }
+
+    #[test]
+    fn backends_invoke_binaries() {
+        for (backend, copy_args, clear_args, clear_input) in [
+            (ClipboardBackend::WlCopy, "", "-c", ""),
+            (
+                ClipboardBackend::Xclip,
+                "-selection clipboard",
+                "-selection clipboard",
+                "",
+            ),
+            (
+                ClipboardBackend::Xsel,
+                "--clipboard --input",
+                "--clipboard --clear",
+                "",
+            ),
+            (
+                ClipboardBackend::Tmux,
+                "load-buffer -b radomsko -w -",
+                "load-buffer -b radomsko -w - ; delete-buffer -b radomsko",
+                " ",
+            ),
+        ] {
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let name = backend.default_program();
+            let stub = stub_binary(tmp_dir.path(), name);
+            let clipboard = Clipboard::new(Some(backend), Some(stub.as_str()), false, false);
+
+            clipboard.copy("hunter2").unwrap();
+            assert_eq!(
+                stub_log(tmp_dir.path(), name),
+                format!("{}\nhunter2", copy_args)
+            );
+            clipboard.clear().unwrap();
+            assert_eq!(
+                stub_log(tmp_dir.path(), name),
+                format!("{}\nhunter2{}\n{}", copy_args, clear_args, clear_input)
+            );
+        }
+    }
+
+    // Creates `wl-copy` and `wl-paste` in `directory` that keep the
+    // clipboard in `clipboard`, and its MIME type in `clipboard.type`,
+    // and record the arguments to `wl-copy` in `wl-copy.log`.
+    fn stub_wayland_clipboard(directory: &Path) -> String {
+        let clipboard = directory.join("clipboard");
+        let log = directory.join("wl-copy.log");
+        for (name, script) in [
+            (
+                "wl-copy",
+                format!(
+                    "#!/bin/sh\necho \"$@\" >> '{log}'\n\
+                     if [ \"$1\" = -c ]; then rm -f '{clipboard}'; exit; fi\n\
+                     cat > '{clipboard}'; echo text/plain > '{clipboard}.type'\n",
+                    log = log.display(),
+                    clipboard = clipboard.display()
+                ),
+            ),
+            (
+                "wl-paste",
+                format!(
+                    "#!/bin/sh\n[ \"$1 $2\" = \"--type $(cat '{clipboard}.type')\" ] || exit 1\n\
+                     cat '{clipboard}' 2>/dev/null || exit 1\n",
+                    clipboard = clipboard.display()
+                ),
+            ),
+        ] {
+            let path = directory.join(name);
+            std::fs::write(&path, script).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        directory.join("wl-copy").to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn restore_puts_back_previous_contents() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let stub = stub_wayland_clipboard(tmp_dir.path());
+        let clipboard = Clipboard::new(
+            Some(ClipboardBackend::WlCopy),
+            Some(stub.as_str()),
+            false,
+            false,
+        );
+
+        clipboard.copy("shopping list").unwrap();
+        let previous = clipboard.paste().unwrap();
+        assert_eq!(previous.as_deref(), Some("shopping list"));
+        clipboard.copy("hunter2").unwrap();
+        clipboard.restore("hunter2", previous.as_deref()).unwrap();
+        assert_eq!(clipboard.paste().unwrap().as_deref(), Some("shopping list"));
+    }
+
+    #[test]
+    fn restore_clears_if_nothing_was_saved() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let stub = stub_wayland_clipboard(tmp_dir.path());
+        let clipboard = Clipboard::new(
+            Some(ClipboardBackend::WlCopy),
+            Some(stub.as_str()),
+            false,
+            false,
+        );
+
+        clipboard.copy("hunter2").unwrap();
+        clipboard.restore("hunter2", None).unwrap();
+        assert!(!tmp_dir.path().join("clipboard").exists());
+    }
+
+    #[test]
+    fn restore_clears_if_paste_fails() {
+        // There is no `wl-paste` alongside this `wl-copy`.
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let stub = stub_binary(tmp_dir.path(), "wl-copy");
+        let clipboard = Clipboard::new(
+            Some(ClipboardBackend::WlCopy),
+            Some(stub.as_str()),
+            false,
+            false,
+        );
+
+        assert!(clipboard.paste().is_err());
+        clipboard.restore("hunter2", None).unwrap();
+        assert_eq!(stub_log(tmp_dir.path(), "wl-copy"), "-c\n");
+    }
+
+    #[test]
+    fn restore_leaves_newer_contents_alone() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let stub = stub_wayland_clipboard(tmp_dir.path());
+        let clipboard = Clipboard::new(
+            Some(ClipboardBackend::WlCopy),
+            Some(stub.as_str()),
+            false,
+            false,
+        );
+
+        clipboard.copy("hunter2").unwrap();
+        clipboard.copy("copied since").unwrap();
+        clipboard.restore("hunter2", Some("shopping list")).unwrap();
+        assert_eq!(clipboard.paste().unwrap().as_deref(), Some("copied since"));
+    }
+
+    #[test]
+    fn paste_once() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let stub = stub_wayland_clipboard(tmp_dir.path());
+        let clipboard = Clipboard::new(
+            Some(ClipboardBackend::WlCopy),
+            Some(stub.as_str()),
+            true,
+            false,
+        );
+
+        clipboard.copy("hunter2").unwrap();
+        assert_eq!(stub_log(tmp_dir.path(), "wl-copy"), "--paste-once\n");
+    }
+
+    #[test]
+    fn paste_ignores_anything_but_text() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let stub = stub_wayland_clipboard(tmp_dir.path());
+        let clipboard = Clipboard::new(
+            Some(ClipboardBackend::WlCopy),
+            Some(stub.as_str()),
+            false,
+            false,
+        );
+
+        std::fs::write(tmp_dir.path().join("clipboard"), b"\x89PNG\r\n").unwrap();
+        std::fs::write(tmp_dir.path().join("clipboard.type"), "image/png\n").unwrap();
+        assert!(clipboard.paste().is_err());
+        std::fs::write(tmp_dir.path().join("clipboard.type"), "text/plain\n").unwrap();
+        assert_eq!(clipboard.paste().unwrap(), None);
+    }
+
+    #[test]
+    fn sensitive_copies_carry_the_hint() {
+        for (backend, copy_args) in [
+            (ClipboardBackend::WlCopy, "--sensitive"),
+            (ClipboardBackend::Xclip, "-selection clipboard -sensitive"),
+            (ClipboardBackend::Xsel, "--clipboard --input"),
+        ] {
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let name = backend.default_program();
+            let stub = stub_binary(tmp_dir.path(), name);
+            let clipboard = Clipboard::new(Some(backend), Some(stub.as_str()), false, true);
+
+            clipboard.copy("hunter2").unwrap();
+            assert_eq!(
+                stub_log(tmp_dir.path(), name),
+                format!("{}\nhunter2", copy_args)
+            );
+        }
+    }
+
+    #[test]
+    fn failing_binary_is_an_error() {
+        let clipboard = Clipboard::new(Some(ClipboardBackend::WlCopy), Some("false"), false, false);
+        let err = clipboard.copy("hunter2").unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            RadomskoError::SubprocessError { .. }
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+// Shell completion and man pages, both derived from the clap `Cli`.
+//
+// Completion is dynamic: the script printed by `radomsko completion`
+// calls back into radomsko (with `COMPLETE` set in its environment) to
+// complete each word, so entry names come from the password store as
+// it is at that moment.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+
+use crate::config::{CommandLineOverrides, Config};
+use crate::enums::{io_error_at, RadomskoError};
+use crate::password_store::PasswordStoreInterface;
+
+// The environment variable that has radomsko complete a command line
+// instead of running it.
+pub const COMPLETE_ENV: &str = "COMPLETE";
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Copy, Clone)]
+pub enum Shell {
+    Bash,
+    Fish,
+    Zsh,
+}
+
+impl Shell {
+    fn name(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Fish => "fish",
+            Shell::Zsh => "zsh",
+        }
+    }
+}
+
+// Writes the script that registers completion of `command` with
+// `shell` to stdout.
+pub fn write_registration(command: &clap::Command, shell: Shell) -> Result<(), RadomskoError> {
+    let shells = clap_complete::env::Shells::builtins();
+    let name = command.get_name();
+    shells
+        .completer(shell.name())
+        .unwrap()
+        .write_registration(COMPLETE_ENV, name, name, name, &mut std::io::stdout())
+        .map_err(|e| RadomskoError::IoError(format!("cannot write completion script: {}", e)))
+}
+
+// Writes a man page for `command` and for each of its subcommands
+// into `directory`.
+pub fn write_man_pages(command: clap::Command, directory: &Path) -> Result<(), RadomskoError> {
+    clap_mangen::generate_to(command, directory)
+        .map_err(io_error_at("write man pages to", directory))
+}
+
+// Picks `--config` and `--store-dir` out of the command line being
+// completed, so that entries come from the store it would act on.
+fn overrides_from(args: &[String]) -> CommandLineOverrides {
+    let mut overrides = CommandLineOverrides {
+        config: None,
+        store_dir: None,
+        clip_timeout: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_owned())),
+            None => (arg.as_str(), None),
+        };
+        let value = match flag {
+            "--config" | "--store-dir" => value.or_else(|| args.next().cloned()),
+            _ => continue,
+        };
+        match flag {
+            "--config" => overrides.config = value.map(PathBuf::from),
+            _ => overrides.store_dir = value,
+        }
+    }
+    overrides
+}
+
+// Completes `current` with the names in the password store, or with
+// only its directories if `directories_only` is set. Any error leaves
+// nothing to complete, as there is nowhere to report it.
+fn complete_name(current: &OsStr, directories_only: bool) -> Vec<CompletionCandidate> {
+    let args: Vec<String> = std::env::args().collect();
+    let password_store = match Config::load(overrides_from(&args))
+        .and_then(|config| PasswordStoreInterface::new(config.store_dir.as_str(), false))
+    {
+        Ok(password_store) => password_store,
+        Err(_) => return Vec::new(),
+    };
+    password_store
+        .completions_for(current.to_str().unwrap_or_default(), directories_only)
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+pub fn entry_completer() -> ArgValueCompleter {
+    ArgValueCompleter::new(|current: &OsStr| complete_name(current, false))
+}
+
+pub fn directory_completer() -> ArgValueCompleter {
+    ArgValueCompleter::new(|current: &OsStr| complete_name(current, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn overrides_from_completed_command_line() {
+        let overrides = overrides_from(&args(&[
+            "radomsko",
+            "--",
+            "radomsko",
+            "--store-dir",
+            "/tmp/store",
+            "--config=/tmp/config.toml",
+            "show",
+            "web/",
+        ]));
+        assert_eq!(overrides.store_dir.as_deref(), Some("/tmp/store"));
+        assert_eq!(overrides.config, Some(PathBuf::from("/tmp/config.toml")));
+
+        let overrides = overrides_from(&args(&["radomsko", "--", "radomsko", "--store-dir"]));
+        assert_eq!(overrides.store_dir, None);
+    }
+}
@@ -0,0 +1,498 @@
+use std::path::{Path, PathBuf};
+
+use crate::clipboard::ClipboardBackend;
+use crate::enums::{io_error_at, RadomskoError};
+use crate::hooks::Hooks;
+
+const CONFIG_ENV: &str = "RADOMSKO_CONFIG";
+const STORE_DIR_ENV: &str = "RADOMSKO_STORE_DIR";
+const CLEARTEXT_DIR_ENV: &str = "RADOMSKO_CLEARTEXT_DIR";
+const CLIP_TIMEOUT_ENV: &str = "RADOMSKO_CLIP_TIMEOUT";
+// A list of paths, separated by colons as in `PATH`.
+const AGE_IDENTITIES_ENV: &str = "RADOMSKO_AGE_IDENTITIES";
+
+// Shared with `pass`. These yield to the `RADOMSKO_*` variables above.
+const PASS_STORE_DIR_ENV: &str = "PASSWORD_STORE_DIR";
+const PASS_CLIP_TIME_ENV: &str = "PASSWORD_STORE_CLIP_TIME";
+const PASS_GPG_OPTS_ENV: &str = "PASSWORD_STORE_GPG_OPTS";
+const PASS_KEY_ENV: &str = "PASSWORD_STORE_KEY";
+const PASS_UMASK_ENV: &str = "PASSWORD_STORE_UMASK";
+
+const DEFAULT_CLIP_TIMEOUT: u64 = 13;
+const DEFAULT_QRENCODE_COMMAND: &str = "qrencode";
+const DEFAULT_UMASK: u32 = 0o077;
+const DEFAULT_HOOK_TIMEOUT: u64 = 5;
+const DEFAULT_CRYPTO_BACKEND: &str = "gpg";
+
+// The resolved configuration of radomsko.
+//
+// Each setting is taken from the first of these that provides it:
+// 1.   command-line flags,
+// 2.   environment variables,
+// 3.   the configuration file,
+// 4.   built-in defaults.
+//
+// Empty `store_dir` and `cleartext_dir` defer to the defaults of
+// `PasswordStoreInterface` and `CleartextHolderInterface`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Config {
+    pub store_dir: String,
+    pub cleartext_dir: String,
+    pub clip_timeout: u64,
+    // `None` to detect the clipboard backend from the environment.
+    pub clipboard_backend: Option<ClipboardBackend>,
+    // Replaces the binary run by the clipboard backend.
+    pub clipboard_command: Option<String>,
+    // Has the clipboard serve the secret only once, if it can.
+    pub clipboard_paste_once: bool,
+    // Has the clipboard tell clipboard managers not to record the
+    // secret, if it can.
+    pub clipboard_sensitive: bool,
+    pub qrencode_command: String,
+    pub hooks: Hooks,
+    // Names the `CryptoBackend` that encrypts and decrypts passwords.
+    pub crypto_backend: String,
+    // The keyring read by in-process backends.
+    pub keyring: Option<PathBuf>,
+    // Identity files that decrypt `.age` passwords.
+    pub age_identities: Vec<PathBuf>,
+    // Extra arguments to every gpg invocation.
+    pub gpg_options: Vec<String>,
+    // Recipients to use instead of those in `.gpg-id` files.
+    pub key: Option<Vec<String>>,
+    pub umask: u32,
+}
+
+// The contents of the configuration file, all of which is optional.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    store_dir: Option<String>,
+    cleartext_dir: Option<String>,
+    clip_timeout: Option<u64>,
+    clipboard_backend: Option<String>,
+    clipboard_command: Option<String>,
+    clipboard_paste_once: Option<bool>,
+    clipboard_sensitive: Option<bool>,
+    qrencode_command: Option<String>,
+    hooks: Option<HooksFile>,
+    crypto_backend: Option<String>,
+    keyring: Option<PathBuf>,
+    age_identities: Option<Vec<PathBuf>>,
+    gpg_options: Option<Vec<String>>,
+    umask: Option<u32>,
+}
+
+// The `[hooks]` table of the configuration file.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HooksFile {
+    after_copy: Option<Vec<String>>,
+    after_clear: Option<Vec<String>>,
+    after_edit: Option<Vec<String>>,
+    after_decrypt_failure: Option<Vec<String>>,
+    timeout: Option<u64>,
+}
+
+// Settings that can be given on the command line.
+#[derive(Debug, Default)]
+pub struct CommandLineOverrides {
+    pub config: Option<PathBuf>,
+    pub store_dir: Option<String>,
+    pub clip_timeout: Option<u64>,
+}
+
+// Returns `$XDG_CONFIG_HOME/radomsko/config.toml`, falling back on
+// `~/.config` if `XDG_CONFIG_HOME` is unset.
+fn default_config_path(env: &dyn Fn(&str) -> Option<String>) -> PathBuf {
+    let mut path = match env("XDG_CONFIG_HOME") {
+        Some(xdg_config_home) if !xdg_config_home.is_empty() => PathBuf::from(xdg_config_home),
+        _ => {
+            let mut home = home::home_dir().unwrap();
+            home.push(".config");
+            home
+        }
+    };
+    path.push("radomsko");
+    path.push("config.toml");
+    path
+}
+
+fn read_config_file(path: &Path, required: bool) -> Result<ConfigFile, RadomskoError> {
+    if !required && !path.exists() {
+        return Ok(ConfigFile::default());
+    }
+    let contents = std::fs::read_to_string(path).map_err(io_error_at("read", path))?;
+    toml::from_str(contents.as_str())
+        .map_err(|e| RadomskoError::BadInput(format!("bad config file {}: {}", path.display(), e)))
+}
+
+fn parse_clip_timeout(value: &str, origin: &str) -> Result<u64, RadomskoError> {
+    value
+        .parse()
+        .map_err(|_| RadomskoError::BadInput(format!("bad clip timeout in {}: {}", origin, value)))
+}
+
+fn parse_umask(value: &str) -> Result<u32, RadomskoError> {
+    match u32::from_str_radix(value, 8) {
+        Ok(umask) if umask <= 0o777 => Ok(umask),
+        _ => Err(RadomskoError::BadInput(format!(
+            "bad umask in {}: {}",
+            PASS_UMASK_ENV, value
+        ))),
+    }
+}
+
+// Splits a list of words given in an environment variable, as `pass`
+// does by leaving the variable unquoted.
+fn split_words(value: &str) -> Vec<String> {
+    value
+        .split_whitespace()
+        .map(|word| word.to_owned())
+        .collect()
+}
+
+impl Config {
+    pub fn load(overrides: CommandLineOverrides) -> Result<Config, RadomskoError> {
+        Config::load_impl(overrides, &|name| std::env::var(name).ok())
+    }
+
+    // Aids `load()`, reading the environment through `env` so that
+    // tests needn't touch the real one.
+    pub fn load_impl(
+        overrides: CommandLineOverrides,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Config, RadomskoError> {
+        // An explicitly named configuration file must exist.
+        let file = match overrides.config.or(env(CONFIG_ENV).map(PathBuf::from)) {
+            Some(path) => read_config_file(&path, true)?,
+            None => read_config_file(&default_config_path(env), false)?,
+        };
+
+        let clip_timeout = match (
+            overrides.clip_timeout,
+            env(CLIP_TIMEOUT_ENV),
+            env(PASS_CLIP_TIME_ENV),
+        ) {
+            (Some(clip_timeout), _, _) => clip_timeout,
+            (None, Some(value), _) => parse_clip_timeout(value.as_str(), CLIP_TIMEOUT_ENV)?,
+            (None, None, Some(value)) => parse_clip_timeout(value.as_str(), PASS_CLIP_TIME_ENV)?,
+            (None, None, None) => file.clip_timeout.unwrap_or(DEFAULT_CLIP_TIMEOUT),
+        };
+        let umask = match env(PASS_UMASK_ENV) {
+            Some(value) => parse_umask(value.as_str())?,
+            None => file.umask.unwrap_or(DEFAULT_UMASK),
+        };
+        let hooks = file.hooks.unwrap_or_default();
+        let key = env(PASS_KEY_ENV)
+            .map(|value| split_words(value.as_str()))
+            .filter(|key| !key.is_empty());
+
+        Ok(Config {
+            store_dir: overrides
+                .store_dir
+                .or(env(STORE_DIR_ENV))
+                .or(env(PASS_STORE_DIR_ENV))
+                .or(file.store_dir)
+                .unwrap_or_default(),
+            cleartext_dir: env(CLEARTEXT_DIR_ENV)
+                .or(file.cleartext_dir)
+                .unwrap_or_default(),
+            clip_timeout,
+            clipboard_backend: match file.clipboard_backend {
+                Some(name) => ClipboardBackend::from_name(name.as_str())?,
+                None => None,
+            },
+            clipboard_command: file.clipboard_command,
+            clipboard_paste_once: file.clipboard_paste_once.unwrap_or(false),
+            clipboard_sensitive: file.clipboard_sensitive.unwrap_or(false),
+            qrencode_command: file
+                .qrencode_command
+                .unwrap_or(DEFAULT_QRENCODE_COMMAND.to_owned()),
+            hooks: Hooks {
+                after_copy: hooks.after_copy.unwrap_or_default(),
+                after_clear: hooks.after_clear.unwrap_or_default(),
+                after_edit: hooks.after_edit.unwrap_or_default(),
+                after_decrypt_failure: hooks.after_decrypt_failure.unwrap_or_default(),
+                timeout: hooks.timeout.unwrap_or(DEFAULT_HOOK_TIMEOUT),
+            },
+            crypto_backend: file
+                .crypto_backend
+                .unwrap_or(DEFAULT_CRYPTO_BACKEND.to_owned()),
+            keyring: file.keyring,
+            age_identities: match env(AGE_IDENTITIES_ENV) {
+                Some(value) => std::env::split_paths(&value)
+                    .filter(|path| !path.as_os_str().is_empty())
+                    .collect(),
+                None => file.age_identities.unwrap_or_default(),
+            },
+            gpg_options: match env(PASS_GPG_OPTS_ENV) {
+                Some(value) => split_words(value.as_str()),
+                None => file.gpg_options.unwrap_or_default(),
+            },
+            key,
+            umask,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Returns a stand-in for `std::env::var()` backed by `vars`.
+    fn fake_env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn config_file(contents: &str) -> tempfile::NamedTempFile {
+        let file = tempfile::Builder::new()
+            .prefix("radomsko-config-")
+            .suffix(".toml")
+            .tempfile()
+            .unwrap();
+        std::fs::write(file.path(), contents).unwrap();
+        file
+    }
+
+    fn with_config(file: &tempfile::NamedTempFile) -> CommandLineOverrides {
+        CommandLineOverrides {
+            config: Some(file.path().to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn defaults_without_config_file() {
+        let xdg_config_home = tempfile::tempdir().unwrap();
+        let env = fake_env(&[("XDG_CONFIG_HOME", xdg_config_home.path().to_str().unwrap())]);
+        let config = Config::load_impl(CommandLineOverrides::default(), &env).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                store_dir: String::new(),
+                cleartext_dir: String::new(),
+                clip_timeout: 13,
+                clipboard_backend: None,
+                clipboard_command: None,
+                clipboard_paste_once: false,
+                clipboard_sensitive: false,
+                qrencode_command: "qrencode".to_string(),
+                hooks: Hooks {
+                    timeout: 5,
+                    ..Default::default()
+                },
+                crypto_backend: "gpg".to_string(),
+                keyring: None,
+                age_identities: vec![],
+                gpg_options: vec![],
+                key: None,
+                umask: 0o077,
+            }
+        );
+    }
+
+    #[test]
+    fn reads_config_file_from_xdg_config_home() {
+        let xdg_config_home = tempfile::tempdir().unwrap();
+        let directory = xdg_config_home.path().join("radomsko");
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::write(directory.join("config.toml"), "clip_timeout = 45\n").unwrap();
+        let env = fake_env(&[("XDG_CONFIG_HOME", xdg_config_home.path().to_str().unwrap())]);
+        let config = Config::load_impl(CommandLineOverrides::default(), &env).unwrap();
+        assert_eq!(config.clip_timeout, 45);
+    }
+
+    #[test]
+    fn config_file_overrides_defaults() {
+        let file = config_file(
+            r#"
+            store_dir = "/srv/store"
+            cleartext_dir = "/run/cleartext"
+            clip_timeout = 30
+            clipboard_backend = "xsel"
+            clipboard_command = "/opt/bin/xsel"
+            clipboard_paste_once = true
+            clipboard_sensitive = true
+            qrencode_command = "/opt/bin/qrencode"
+            crypto_backend = "sequoia"
+            keyring = "/srv/keyring.pgp"
+            age_identities = ["/srv/identities"]
+            gpg_options = ["--pinentry-mode", "loopback"]
+            umask = 0o027
+
+            [hooks]
+            after_copy = ["niri", "msg", "action", "focus-monitor-up"]
+            after_clear = ["notify-send", "cleared"]
+            timeout = 2
+            "#,
+        );
+        let config = Config::load_impl(with_config(&file), &fake_env(&[])).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                store_dir: "/srv/store".to_string(),
+                cleartext_dir: "/run/cleartext".to_string(),
+                clip_timeout: 30,
+                clipboard_backend: Some(ClipboardBackend::Xsel),
+                clipboard_command: Some("/opt/bin/xsel".to_string()),
+                clipboard_paste_once: true,
+                clipboard_sensitive: true,
+                qrencode_command: "/opt/bin/qrencode".to_string(),
+                hooks: Hooks {
+                    after_copy: vec![
+                        "niri".to_string(),
+                        "msg".to_string(),
+                        "action".to_string(),
+                        "focus-monitor-up".to_string()
+                    ],
+                    after_clear: vec!["notify-send".to_string(), "cleared".to_string()],
+                    after_edit: vec![],
+                    after_decrypt_failure: vec![],
+                    timeout: 2,
+                },
+                crypto_backend: "sequoia".to_string(),
+                keyring: Some(PathBuf::from("/srv/keyring.pgp")),
+                age_identities: vec![PathBuf::from("/srv/identities")],
+                gpg_options: vec!["--pinentry-mode".to_string(), "loopback".to_string()],
+                key: None,
+                umask: 0o027,
+            }
+        );
+    }
+
+    #[test]
+    fn environment_overrides_config_file() {
+        let file = config_file(
+            "store_dir = \"/srv/store\"\nclip_timeout = 30\nage_identities = [\"/srv/identities\"]\n",
+        );
+        let env = fake_env(&[
+            (STORE_DIR_ENV, "/env/store"),
+            (CLIP_TIMEOUT_ENV, "5"),
+            (CLEARTEXT_DIR_ENV, "/env/cleartext"),
+            (AGE_IDENTITIES_ENV, "/env/work:/env/personal"),
+        ]);
+        let config = Config::load_impl(with_config(&file), &env).unwrap();
+        assert_eq!(config.store_dir, "/env/store");
+        assert_eq!(config.clip_timeout, 5);
+        assert_eq!(config.cleartext_dir, "/env/cleartext");
+        assert_eq!(
+            config.age_identities,
+            vec![PathBuf::from("/env/work"), PathBuf::from("/env/personal")]
+        );
+    }
+
+    #[test]
+    fn honors_pass_environment() {
+        let file = config_file("store_dir = \"/srv/store\"\ngpg_options = [\"--batch\"]\n");
+        let env = fake_env(&[
+            (PASS_STORE_DIR_ENV, "/pass/store"),
+            (PASS_CLIP_TIME_ENV, "20"),
+            (PASS_GPG_OPTS_ENV, "--no-tty  --trust-model always"),
+            (PASS_KEY_ENV, "0xDEADBEEF 0xCAFEF00D"),
+            (PASS_UMASK_ENV, "022"),
+        ]);
+        let config = Config::load_impl(with_config(&file), &env).unwrap();
+        assert_eq!(config.store_dir, "/pass/store");
+        assert_eq!(config.clip_timeout, 20);
+        assert_eq!(
+            config.gpg_options,
+            vec!["--no-tty", "--trust-model", "always"]
+        );
+        assert_eq!(
+            config.key,
+            Some(vec!["0xDEADBEEF".to_string(), "0xCAFEF00D".to_string()])
+        );
+        assert_eq!(config.umask, 0o022);
+    }
+
+    #[test]
+    fn radomsko_environment_overrides_pass_environment() {
+        let file = config_file("");
+        let env = fake_env(&[
+            (STORE_DIR_ENV, "/env/store"),
+            (PASS_STORE_DIR_ENV, "/pass/store"),
+            (CLIP_TIMEOUT_ENV, "5"),
+            (PASS_CLIP_TIME_ENV, "20"),
+        ]);
+        let config = Config::load_impl(with_config(&file), &env).unwrap();
+        assert_eq!(config.store_dir, "/env/store");
+        assert_eq!(config.clip_timeout, 5);
+    }
+
+    #[test]
+    fn rejects_bad_umask() {
+        let file = config_file("");
+        for umask in ["rwx", "0888", "1777"] {
+            let env = fake_env(&[(PASS_UMASK_ENV, umask)]);
+            let err = Config::load_impl(with_config(&file), &env).unwrap_err();
+            assert!(matches!(err, RadomskoError::BadInput { .. }), "{}", umask);
+        }
+    }
+
+    #[test]
+    fn command_line_overrides_environment() {
+        let file = config_file("store_dir = \"/srv/store\"\n");
+        let env = fake_env(&[(STORE_DIR_ENV, "/env/store"), (CLIP_TIMEOUT_ENV, "5")]);
+        let overrides = CommandLineOverrides {
+            config: Some(file.path().to_path_buf()),
+            store_dir: Some("/cli/store".to_string()),
+            clip_timeout: Some(1),
+        };
+        let config = Config::load_impl(overrides, &env).unwrap();
+        assert_eq!(config.store_dir, "/cli/store");
+        assert_eq!(config.clip_timeout, 1);
+    }
+
+    #[test]
+    fn config_file_named_in_environment() {
+        let file = config_file("clip_timeout = 99\n");
+        let env = fake_env(&[(CONFIG_ENV, file.path().to_str().unwrap())]);
+        let config = Config::load_impl(CommandLineOverrides::default(), &env).unwrap();
+        assert_eq!(config.clip_timeout, 99);
+    }
+
+    #[test]
+    fn explicit_config_file_must_exist() {
+        let overrides = CommandLineOverrides {
+            config: Some(PathBuf::from("/nonexistent/radomsko.toml")),
+            ..Default::default()
+        };
+        let err = Config::load_impl(overrides, &fake_env(&[])).unwrap_err();
+        assert!(matches!(err, RadomskoError::IoError { .. }));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let file = config_file("clip_timout = 30\n");
+        let err = Config::load_impl(with_config(&file), &fake_env(&[])).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn rejects_unknown_hooks() {
+        let file = config_file("[hooks]\nafter_paste = [\"true\"]\n");
+        let err = Config::load_impl(with_config(&file), &fake_env(&[])).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn rejects_unknown_clipboard_backend() {
+        let file = config_file("clipboard_backend = \"pbcopy\"\n");
+        let err = Config::load_impl(with_config(&file), &fake_env(&[])).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn rejects_bad_clip_timeout_in_environment() {
+        let env = fake_env(&[(CLIP_TIMEOUT_ENV, "soon")]);
+        let file = config_file("");
+        let err = Config::load_impl(with_config(&file), &env).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+}
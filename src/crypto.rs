@@ -0,0 +1,59 @@
+// Encrypts and decrypts passwords through one of several backends.
+
+use std::path::Path;
+
+use crate::config::Config;
+use crate::enums::RadomskoError;
+use crate::external_commands;
+
+pub trait CryptoBackend {
+    // Returns the cleartext of the encrypted `password`.
+    fn decrypt(&self, password: &Path) -> Result<String, RadomskoError>;
+
+    // Encrypts `cleartext` to `recipients` (as listed in recipients
+    // files) without ever writing it to disk.
+    fn encrypt(&self, cleartext: &str, recipients: &[String]) -> Result<Vec<u8>, RadomskoError>;
+}
+
+// Runs the `gpg` binary, which finds keys in its own keyring and
+// secrets through gpg-agent.
+pub struct GpgBackend {
+    gpg_options: Vec<String>,
+}
+
+impl CryptoBackend for GpgBackend {
+    fn decrypt(&self, password: &Path) -> Result<String, RadomskoError> {
+        external_commands::decrypt_password_to_string(password, &self.gpg_options)
+    }
+
+    fn encrypt(&self, cleartext: &str, recipients: &[String]) -> Result<Vec<u8>, RadomskoError> {
+        external_commands::encrypt_string(cleartext, recipients, &self.gpg_options)
+    }
+}
+
+// Returns the OpenPGP backend named in `config`, which handles `.gpg`
+// passwords.
+pub fn backend_for(config: &Config) -> Result<Box<dyn CryptoBackend>, RadomskoError> {
+    match config.crypto_backend.as_str() {
+        "gpg" => Ok(Box::new(GpgBackend {
+            gpg_options: config.gpg_options.clone(),
+        })),
+        #[cfg(feature = "sequoia")]
+        "sequoia" => match &config.keyring {
+            Some(keyring) => Ok(Box::new(crate::sequoia_backend::SequoiaBackend::new(
+                keyring,
+            )?)),
+            None => Err(RadomskoError::BadInput(
+                "the sequoia backend needs a keyring".to_string(),
+            )),
+        },
+        #[cfg(not(feature = "sequoia"))]
+        "sequoia" => Err(RadomskoError::BadInput(
+            "radomsko was built without the sequoia feature".to_string(),
+        )),
+        other => Err(RadomskoError::BadInput(format!(
+            "unknown crypto backend ``{}''",
+            other
+        ))),
+    }
+}
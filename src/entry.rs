@@ -0,0 +1,146 @@
+// Helpers for picking apart the decrypted contents of a password.
+
+use crate::enums::RadomskoError;
+
+// Returns the 1-indexed `line` of `cleartext`.
+pub fn line_of(cleartext: &str, line: usize) -> Result<&str, RadomskoError> {
+    if line == 0 {
+        return Err(RadomskoError::BadInput(
+            "line numbers start at 1".to_string(),
+        ));
+    }
+    match cleartext.lines().nth(line - 1) {
+        Some(found) => Ok(found),
+        None => Err(RadomskoError::BadInput(format!(
+            "entry has no line {}",
+            line
+        ))),
+    }
+}
+
+// A password parsed according to the convention of keeping the
+// password itself on the first line, followed by `key: value` fields.
+// Lines that aren't fields (e.g. free-form notes) are ignored.
+#[derive(Debug)]
+pub struct Entry<'a> {
+    password: &'a str,
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+// Splits `line` into a key and value if it looks like a field.
+fn parse_field(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    let key = key.trim();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    // Insists on whitespace after the colon so that URIs (e.g.
+    // `otpauth://...`) aren't mistaken for fields.
+    if !value.is_empty() && !value.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some((key, value.trim()))
+}
+
+impl<'a> Entry<'a> {
+    pub fn parse(cleartext: &'a str) -> Entry<'a> {
+        let mut lines = cleartext.lines();
+        let password = lines.next().unwrap_or("");
+        Entry {
+            password,
+            fields: lines.filter_map(parse_field).collect(),
+        }
+    }
+
+    pub fn password(&self) -> &'a str {
+        self.password
+    }
+
+    // Returns the value of the first field named `key`, ignoring case.
+    pub fn field(&self, key: &str) -> Option<&'a str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| *value)
+    }
+
+    // Returns the distinct field names in order of appearance.
+    pub fn field_names(&self) -> Vec<&'a str> {
+        let mut result: Vec<&'a str> = Vec::new();
+        for (name, _) in self.fields.iter() {
+            if !result.iter().any(|seen| seen.eq_ignore_ascii_case(name)) {
+                result.push(name);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    const STRUCTURED: &str = indoc! {"
+        hunter2
+        username: klaus
+        URL: https://example.com/login
+        otpauth://totp/example?secret=JBSWY3DPEHPK3PXP
+        Some free-form notes: these are not a field
+        empty:
+        username: shadowed
+    "};
+
+    const CLEARTEXT: &str = "hunter2\nusername: klaus\n\nurl: example.com\n";
+
+    #[test]
+    fn line_of_first_line() {
+        assert_eq!(line_of(CLEARTEXT, 1).unwrap(), "hunter2");
+    }
+
+    #[test]
+    fn line_of_later_lines() {
+        assert_eq!(line_of(CLEARTEXT, 2).unwrap(), "username: klaus");
+        assert_eq!(line_of(CLEARTEXT, 3).unwrap(), "");
+        assert_eq!(line_of(CLEARTEXT, 4).unwrap(), "url: example.com");
+    }
+
+    #[test]
+    fn line_of_out_of_range() {
+        let err = line_of(CLEARTEXT, 5).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+        let err = line_of(CLEARTEXT, 0).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn parse_password() {
+        assert_eq!(Entry::parse(STRUCTURED).password(), "hunter2");
+        assert_eq!(Entry::parse("").password(), "");
+    }
+
+    #[test]
+    fn parse_fields() {
+        let entry = Entry::parse(STRUCTURED);
+        assert_eq!(entry.field("username"), Some("klaus"));
+        assert_eq!(entry.field("url"), Some("https://example.com/login"));
+        assert_eq!(entry.field("empty"), Some(""));
+        assert_eq!(entry.field("otpauth"), None);
+        assert_eq!(entry.field("notes"), None);
+    }
+
+    #[test]
+    fn field_names_are_distinct_and_ordered() {
+        assert_eq!(
+            Entry::parse(STRUCTURED).field_names(),
+            vec!["username", "URL", "empty"]
+        );
+    }
+
+    #[test]
+    fn password_line_is_never_a_field() {
+        let entry = Entry::parse("username: klaus\n");
+        assert_eq!(entry.password(), "username: klaus");
+        assert!(entry.field_names().is_empty());
+    }
+}
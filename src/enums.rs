@@ -1,23 +1,156 @@
+use std::path::{Path, PathBuf};
+
+// Everything that can go wrong in radomsko. Each error names what it
+// concerns (an entry, a path, a recipient) so that it reads well on its
+// own, and maps to an exit status that scripts can rely on (see
+// `exit_code()`).
 #[derive(Debug, PartialEq)]
 pub enum RadomskoError {
-    NotFound,
-    BadPermissions,
+    // The named entry or directory is not in the password store.
+    NotFound(String),
+    // The path is accessible to others than its owner.
+    BadPermissions(PathBuf),
+    // The named entry already exists.
+    AlreadyExists(String),
+    BadInput(String),
+    NoRecipients(String),
     IoError(String),
     SubprocessError(String),
+    CryptoError(String),
+    // gpg holds none of the secret keys (listed by key ID) that the
+    // password was encrypted to.
+    NoSecretKey(Vec<String>),
+    PinentryCancelled,
+    BadPassphrase,
+    // A recipient gpg cannot encrypt to, with the reason why.
+    InvalidRecipient(String, String),
+    DecryptionFailed(String),
+    // A key that radomsko cannot use, with the reason why.
+    UnsupportedKey(String),
+    // Another error, prefixed with what was being done when it
+    // happened (e.g. "cannot decrypt ``web/example.com''").
+    Context(String, Box<RadomskoError>),
 }
 
-impl From<std::io::Error> for RadomskoError {
-    fn from(err: std::io::Error) -> RadomskoError {
-        RadomskoError::IoError(err.to_string())
+impl RadomskoError {
+    // Wraps `self` in a description of what was being done.
+    pub fn context(self, context: impl Into<String>) -> RadomskoError {
+        RadomskoError::Context(context.into(), Box::new(self))
+    }
+
+    // Returns `self` without any `Context` wrapped around it.
+    pub fn root_cause(&self) -> &RadomskoError {
+        match self {
+            RadomskoError::Context(_, inner) => inner.root_cause(),
+            _ => self,
+        }
+    }
+
+    // Returns the exit status of radomsko on this error, which lets
+    // wrappers react to particular failures (e.g. retry when the
+    // pinentry was cancelled). Keep `EXIT_STATUS_HELP` in sync.
+    pub fn exit_code(&self) -> i32 {
+        match self.root_cause() {
+            RadomskoError::NotFound(_) => 3,
+            RadomskoError::AlreadyExists(_) => 4,
+            RadomskoError::BadInput(_) => 5,
+            RadomskoError::BadPermissions(_) => 6,
+            RadomskoError::NoRecipients(_) => 7,
+            RadomskoError::DecryptionFailed(_) | RadomskoError::CryptoError(_) => 10,
+            RadomskoError::NoSecretKey(_) => 11,
+            RadomskoError::BadPassphrase => 12,
+            RadomskoError::PinentryCancelled => 13,
+            RadomskoError::InvalidRecipient(_, _) => 14,
+            RadomskoError::UnsupportedKey(_) => 15,
+            RadomskoError::IoError(_) | RadomskoError::SubprocessError(_) => 1,
+            RadomskoError::Context(_, _) => unreachable!(),
+        }
     }
 }
 
-impl From<std::env::VarError> for RadomskoError {
-    fn from(_err: std::env::VarError) -> RadomskoError {
-        RadomskoError::NotFound
+// Documents `RadomskoError::exit_code()` for `--help`.
+pub const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0   success
+  1   I/O or subprocess failure
+  2   bad command-line usage
+  3   entry not found
+  4   entry already exists
+  5   bad input or configuration
+  6   bad permissions on the cleartext or runtime directory
+  7   no recipients for the entry
+  10  decryption failed
+  11  no secret key for the entry
+  12  bad passphrase
+  13  passphrase entry cancelled
+  14  unusable recipient
+  15  unsupported key type";
+
+impl std::fmt::Display for RadomskoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RadomskoError::NotFound(name) => {
+                write!(f, "``{}'' is not in the password store", name)
+            }
+            RadomskoError::BadPermissions(path) => write!(
+                f,
+                "``{}'' must only be accessible to its owner",
+                path.display()
+            ),
+            RadomskoError::AlreadyExists(name) => write!(f, "``{}'' already exists", name),
+            RadomskoError::BadInput(message)
+            | RadomskoError::NoRecipients(message)
+            | RadomskoError::IoError(message)
+            | RadomskoError::SubprocessError(message)
+            | RadomskoError::CryptoError(message) => write!(f, "{}", message),
+            RadomskoError::UnsupportedKey(message) => write!(f, "unsupported key: {}", message),
+            RadomskoError::NoSecretKey(key_ids) => write!(
+                f,
+                "no secret key to decrypt with (encrypted to {})",
+                key_ids.join(", ")
+            ),
+            RadomskoError::PinentryCancelled => write!(f, "passphrase entry was cancelled"),
+            RadomskoError::BadPassphrase => write!(f, "bad passphrase"),
+            RadomskoError::InvalidRecipient(recipient, reason) => {
+                write!(f, "cannot encrypt to ``{}'': {}", recipient, reason)
+            }
+            RadomskoError::DecryptionFailed(messages) => {
+                write!(f, "decryption failed: {}", messages)
+            }
+            RadomskoError::Context(context, inner) => write!(f, "{}: {}", context, inner),
+        }
     }
 }
 
+impl std::error::Error for RadomskoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RadomskoError::Context(_, inner) => Some(inner.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// Returns a mapper for `map_err()` that notes which `operation` on
+// `path` failed.
+pub fn io_error_at<'a>(
+    operation: &'a str,
+    path: &'a Path,
+) -> impl FnOnce(std::io::Error) -> RadomskoError + 'a {
+    move |err| RadomskoError::IoError(format!("cannot {} {}: {}", operation, path.display(), err))
+}
+
+// Like `io_error_at()`, for an `operation` on no path in particular.
+pub fn io_error(operation: &str) -> impl FnOnce(std::io::Error) -> RadomskoError + '_ {
+    move |err| RadomskoError::IoError(format!("cannot {}: {}", operation, err))
+}
+
+// Returns a mapper for `map_err()` that notes which `program` failed
+// to run.
+pub fn popen_error_for(program: &str) -> impl FnOnce(subprocess::PopenError) -> RadomskoError + '_ {
+    move |err| RadomskoError::SubprocessError(format!("cannot run {}: {}", program, err))
+}
+
 impl From<subprocess::PopenError> for RadomskoError {
     fn from(err: subprocess::PopenError) -> RadomskoError {
         RadomskoError::SubprocessError(err.to_string())
@@ -30,3 +163,65 @@ pub enum ShowDestination {
     Clip,
     QrCode,
 }
+
+// How a listing of passwords (from `find`, or `show` on a directory)
+// is laid out.
+#[derive(clap::ValueEnum, Debug, PartialEq, Copy, Clone)]
+pub enum ListingFormat {
+    // An indented tree, highlighted on a terminal.
+    Tree,
+    // One name per line.
+    List,
+    // Directories nesting their contents.
+    Json,
+    // NUL-terminated names, for `xargs -0`.
+    Null,
+}
+
+// Which part of a password to show.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ShowSelection {
+    // The whole password on stdout and the first line elsewhere.
+    Default,
+    Line(usize),
+    Field(String),
+    FieldNames,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_names_what_went_wrong() {
+        assert_eq!(
+            RadomskoError::NotFound("web/example.com".to_string()).to_string(),
+            "``web/example.com'' is not in the password store"
+        );
+        assert_eq!(
+            RadomskoError::BadPermissions(PathBuf::from("/run/user/1000")).to_string(),
+            "``/run/user/1000'' must only be accessible to its owner"
+        );
+        assert_eq!(
+            io_error_at("read", Path::new("/nonexistent"))(std::io::Error::from(
+                std::io::ErrorKind::NotFound
+            ))
+            .to_string(),
+            "cannot read /nonexistent: entity not found"
+        );
+    }
+
+    #[test]
+    fn context_keeps_exit_code() {
+        let err = RadomskoError::BadPassphrase
+            .context("cannot decrypt ``web/example.com''")
+            .context("cannot edit ``web/example.com''");
+        assert_eq!(
+            err.to_string(),
+            "cannot edit ``web/example.com'': cannot decrypt ``web/example.com'': bad passphrase"
+        );
+        assert_eq!(err.root_cause(), &RadomskoError::BadPassphrase);
+        assert_eq!(err.exit_code(), 12);
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
@@ -2,23 +2,47 @@
 // a common implementation in that they act outside the main body of
 // radomsko through external binaries.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use subprocess::{Exec, ExitStatus::*};
 
-use crate::enums::RadomskoError;
-use crate::enums::ShowDestination;
+use crate::clipboard::Clipboard;
+use crate::config::Config;
+use crate::enums::{popen_error_for, RadomskoError, ShowDestination};
+use crate::gpg_status::GpgStatus;
 
-const DISPLAY: &'static str = "DISPLAY";
+const DISPLAY: &str = "DISPLAY";
 
-fn gpg_decrypt_command(password: &Path) -> Exec {
+// `gpg_options` are extra arguments passed to every gpg invocation.
+// Status lines go to stderr, which is captured and parsed by
+// `GpgStatus`.
+fn gpg_command(gpg_options: &[String]) -> Exec {
     Exec::cmd("gpg")
-        .arg("--quiet")
+        .args(gpg_options)
+        .arg("--status-fd")
+        .arg("2")
+        .stdout(subprocess::Redirection::Pipe)
+        .stderr(subprocess::Redirection::Pipe)
+}
+
+// Not `--quiet`, which withholds the status lines that tell why
+// decryption failed.
+fn gpg_decrypt_command(password: &Path, gpg_options: &[String]) -> Exec {
+    gpg_command(gpg_options)
         .arg("-d")
         .arg(password.to_str().unwrap())
         .env_remove(DISPLAY)
 }
 
-fn return_exit_status(status: subprocess::ExitStatus) -> Result<(), RadomskoError> {
+fn gpg_encrypt_command(recipients: &[String], gpg_options: &[String]) -> Exec {
+    assert!(!recipients.is_empty());
+    let mut command = gpg_command(gpg_options).arg("--quiet").arg("-e");
+    for recipient in recipients {
+        command = command.arg("-r").arg(recipient);
+    }
+    command
+}
+
+pub fn return_exit_status(status: subprocess::ExitStatus) -> Result<(), RadomskoError> {
     match status {
         Exited(code) => {
             if code == 0 {
@@ -41,80 +65,261 @@ fn return_exit_status(status: subprocess::ExitStatus) -> Result<(), RadomskoErro
 }
 
 pub fn invoke_editor(password_path: &Path) -> Result<(), RadomskoError> {
-    let editor = std::env::var("EDITOR")?;
-    let status = Exec::cmd(editor)
+    let editor = std::env::var("EDITOR")
+        .map_err(|_| RadomskoError::BadInput("EDITOR is not set".to_string()))?;
+    let status = Exec::cmd(editor.as_str())
         .arg(password_path.to_str().unwrap())
-        .join()?;
-    return_exit_status(status)
-}
-
-pub fn clear_clipboard() -> Result<(), RadomskoError> {
-    let status = Exec::cmd("wl-copy").arg("-c").join()?;
-    return_exit_status(status)
+        .join()
+        .map_err(popen_error_for(editor.as_str()))?;
+    return_exit_status(status).map_err(|e| e.context(format!("editor {}", editor)))
 }
 
-pub fn decrypt_password(password: &Path, dest: ShowDestination) -> Result<(), RadomskoError> {
-    let decrypted = decrypt_password_to_string(password)?;
-
+// Sends already-decrypted `cleartext` to `dest`.
+pub fn show_cleartext(
+    cleartext: &str,
+    dest: ShowDestination,
+    clipboard: &Clipboard,
+    config: &Config,
+) -> Result<(), RadomskoError> {
     // This does a lot more than I want it to, but none of my passwords
     // ever start or end with whitespace, so it is safe for me.
-    let trimmed = decrypted.trim();
-    let status: subprocess::ExitStatus;
-    match dest {
+    let trimmed = cleartext.trim();
+    let status = match dest {
         ShowDestination::Stdout => {
             println!("{}", trimmed);
-            status = subprocess::ExitStatus::Exited(0);
-        }
-        ShowDestination::Clip => {
-            status = Exec::cmd("wl-copy")
-                .stdin(trimmed)
-                .stdout(subprocess::NullFile)
-                .stderr(subprocess::NullFile)
-                .capture()?
-                .exit_status;
+            subprocess::ExitStatus::Exited(0)
         }
+        ShowDestination::Clip => return clipboard.copy(trimmed),
         ShowDestination::QrCode => {
-            status = Exec::cmd("qrencode")
+            Exec::cmd(config.qrencode_command.as_str())
                 .arg("-t")
                 .arg("utf8")
                 .stdin(trimmed)
                 .stdout(subprocess::Redirection::None)
                 .stderr(subprocess::NullFile)
-                .capture()?
-                .exit_status;
+                .capture()
+                .map_err(popen_error_for(config.qrencode_command.as_str()))?
+                .exit_status
         }
-    }
-    return_exit_status(status)
+    };
+    return_exit_status(status).map_err(|e| e.context(config.qrencode_command.as_str()))
 }
 
-pub fn decrypt_password_to_string(password: &Path) -> Result<String, RadomskoError> {
-    let capture_data = gpg_decrypt_command(password).capture()?;
+pub fn decrypt_password_to_string(
+    password: &Path,
+    gpg_options: &[String],
+) -> Result<String, RadomskoError> {
+    let capture_data = gpg_decrypt_command(password, gpg_options)
+        .capture()
+        .map_err(popen_error_for("gpg"))?;
     if !capture_data.success() {
-        return Err(RadomskoError::SubprocessError(format!(
-            "failed to decrypt: ``{}''",
-            capture_data.stderr_str()
-        )));
+        return Err(GpgStatus::parse(capture_data.stderr_str().as_str()).decrypt_error());
     }
     Ok(capture_data.stdout_str())
 }
 
-pub fn encrypt_cleartext(cleartext: &Path) -> Result<(), RadomskoError> {
-    let status = Exec::cmd("gpg")
-        .arg("--quiet")
-        .arg("-e")
-        .arg(cleartext.to_str().unwrap())
+// Encrypts `cleartext` without ever writing it to disk and returns the
+// encrypted output.
+pub fn encrypt_string(
+    cleartext: &str,
+    recipients: &[String],
+    gpg_options: &[String],
+) -> Result<Vec<u8>, RadomskoError> {
+    let capture_data = gpg_encrypt_command(recipients, gpg_options)
+        .stdin(cleartext)
         .env_remove(DISPLAY)
-        .join()?;
-    return_exit_status(status)
+        .capture()
+        .map_err(popen_error_for("gpg"))?;
+    if !capture_data.success() {
+        return Err(GpgStatus::parse(capture_data.stderr_str().as_str()).encrypt_error());
+    }
+    Ok(capture_data.stdout)
+}
+
+fn git_command(repository: &Path) -> Exec {
+    Exec::cmd("git").arg("-C").arg(repository.to_str().unwrap())
 }
 
-pub fn switch_workspace() -> Result<(), RadomskoError> {
-    let status = Exec::cmd("niri")
-        .arg("msg")
-        .arg("action")
-        .arg("focus-monitor-up")
-        .stdout(subprocess::NullFile)
-        .stderr(subprocess::NullFile)
-        .join()?;
-    return_exit_status(status)
+pub fn is_git_repository(path: &Path) -> bool {
+    path.join(".git").exists()
+}
+
+// Returns whether git tracks anything at `path` in `repository`.
+fn is_tracked(repository: &Path, path: &Path) -> Result<bool, RadomskoError> {
+    let capture_data = git_command(repository)
+        .arg("ls-files")
+        .arg("--")
+        .arg(path.to_str().unwrap())
+        .stdout(subprocess::Redirection::Pipe)
+        .capture()
+        .map_err(popen_error_for("git"))?;
+    return_exit_status(capture_data.exit_status).map_err(|e| e.context("git ls-files"))?;
+    Ok(!capture_data.stdout.is_empty())
+}
+
+// Stages the changes to `paths` in the git `repository` and commits
+// them with `message`. Does nothing if there is nothing to commit.
+//
+// Removed paths that git never tracked are skipped, as `git add`
+// would fail to match them.
+pub fn git_commit(
+    repository: &Path,
+    paths: &[PathBuf],
+    message: &str,
+) -> Result<(), RadomskoError> {
+    let mut stageable: Vec<&PathBuf> = Vec::new();
+    for path in paths {
+        if path.exists() || is_tracked(repository, path)? {
+            stageable.push(path);
+        }
+    }
+    // Without any paths, `git add -A` would stage the whole tree.
+    if stageable.is_empty() {
+        return Ok(());
+    }
+
+    let mut add = git_command(repository).arg("add").arg("-A").arg("--");
+    for path in stageable {
+        add = add.arg(path.to_str().unwrap());
+    }
+    return_exit_status(add.join().map_err(popen_error_for("git"))?)
+        .map_err(|e| e.context("git add"))?;
+
+    let staged = git_command(repository)
+        .arg("diff")
+        .arg("--cached")
+        .arg("--quiet")
+        .join()
+        .map_err(popen_error_for("git"))?;
+    if staged.success() {
+        return Ok(());
+    }
+
+    let status = git_command(repository)
+        .arg("commit")
+        .arg("--quiet")
+        .arg("-m")
+        .arg(message)
+        .join()
+        .map_err(popen_error_for("git"))?;
+    return_exit_status(status).map_err(|e| e.context("git commit"))
+}
+
+// Runs git in `repository` with user-supplied `args`, passing through
+// the standard streams.
+pub fn git_passthrough(repository: &Path, args: &[String]) -> Result<(), RadomskoError> {
+    let status = git_command(repository)
+        .args(args)
+        .join()
+        .map_err(popen_error_for("git"))?;
+    return_exit_status(status).map_err(|e| e.context("git"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git_output(repository: &Path, args: &[&str]) -> String {
+        git_command(repository)
+            .args(args)
+            .stdout(subprocess::Redirection::Pipe)
+            .capture()
+            .unwrap()
+            .stdout_str()
+    }
+
+    fn git_repository() -> tempfile::TempDir {
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("git-fixture-")
+            .tempdir()
+            .unwrap();
+        let root = tmp_dir.path();
+        for args in [
+            vec!["init", "--quiet"],
+            vec!["config", "user.name", "radomsko"],
+            vec!["config", "user.email", "radomsko@example.com"],
+            vec!["config", "commit.gpgsign", "false"],
+        ] {
+            assert!(git_command(root).args(&args).join().unwrap().success());
+        }
+        tmp_dir
+    }
+
+    #[test]
+    fn is_git_repository_detects_work_tree() {
+        let repository = git_repository();
+        assert!(is_git_repository(repository.path()));
+        let plain = tempfile::tempdir().unwrap();
+        assert!(!is_git_repository(plain.path()));
+    }
+
+    #[test]
+    fn git_commit_commits_additions_and_removals() {
+        let repository = git_repository();
+        let password = repository.path().join("a.gpg");
+        std::fs::write(&password, "encrypted").unwrap();
+        git_commit(repository.path(), std::slice::from_ref(&password), "Add a").unwrap();
+        assert_eq!(
+            git_output(repository.path(), &["log", "--format=%s"]),
+            "Add a\n"
+        );
+
+        std::fs::remove_file(&password).unwrap();
+        git_commit(
+            repository.path(),
+            std::slice::from_ref(&password),
+            "Remove a",
+        )
+        .unwrap();
+        assert_eq!(
+            git_output(repository.path(), &["log", "--format=%s"]),
+            "Remove a\nAdd a\n"
+        );
+        assert_eq!(git_output(repository.path(), &["ls-files"]), "");
+    }
+
+    #[test]
+    fn git_commit_skips_untracked_removals() {
+        let repository = git_repository();
+        let password = repository.path().join("a.gpg");
+        let untracked = repository.path().join("b.gpg");
+        std::fs::write(&password, "encrypted").unwrap();
+        git_commit(
+            repository.path(),
+            &[password.clone(), untracked.clone()],
+            "Add a",
+        )
+        .unwrap();
+        assert_eq!(git_output(repository.path(), &["ls-files"]), "a.gpg\n");
+
+        git_commit(repository.path(), &[untracked], "Remove b").unwrap();
+        assert_eq!(
+            git_output(repository.path(), &["log", "--format=%s"]),
+            "Add a\n"
+        );
+    }
+
+    #[test]
+    fn git_commit_only_stages_given_paths() {
+        let repository = git_repository();
+        let password = repository.path().join("a.gpg");
+        let bystander = repository.path().join("b.gpg");
+        std::fs::write(&password, "encrypted").unwrap();
+        std::fs::write(&bystander, "encrypted").unwrap();
+        git_commit(repository.path(), &[password], "Add a").unwrap();
+        assert_eq!(git_output(repository.path(), &["ls-files"]), "a.gpg\n");
+    }
+
+    #[test]
+    fn git_commit_skips_empty_commit() {
+        let repository = git_repository();
+        let password = repository.path().join("a.gpg");
+        std::fs::write(&password, "encrypted").unwrap();
+        git_commit(repository.path(), std::slice::from_ref(&password), "Add a").unwrap();
+        git_commit(repository.path(), &[password], "Add a again").unwrap();
+        assert_eq!(
+            git_output(repository.path(), &["log", "--format=%s"]),
+            "Add a\n"
+        );
+    }
 }
@@ -0,0 +1,514 @@
+// Talks to gpg-agent over its Assuan socket so that the sequoia backend
+// can decrypt with secret keys that only the agent holds, prompting
+// through the agent's own pinentry.
+//
+// See the Assuan manual and agent/command.c in the GnuPG sources for
+// the protocol and the commands used here.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use sequoia_openpgp as openpgp;
+
+use openpgp::crypto::mem::Protected;
+use openpgp::crypto::mpi::{Ciphertext, PublicKey, MPI};
+use openpgp::crypto::{Decryptor, SessionKey};
+use openpgp::packet::key::{PublicParts, UnspecifiedRole};
+use openpgp::packet::Key;
+use openpgp::types::Curve;
+use sha1::{Digest, Sha1};
+use subprocess::Exec;
+
+use crate::enums::{io_error, io_error_at, popen_error_for, RadomskoError};
+use crate::external_commands::return_exit_status;
+use crate::gpg_status::{GPG_ERR_BAD_PASSPHRASE, GPG_ERR_CANCELED, GPG_ERR_FULLY_CANCELED};
+
+const TALK: &str = "talk to gpg-agent";
+
+// Assuan lines are at most 1000 bytes long, newline included. Escaping
+// can triple the length of data, hence the size of each chunk of it.
+const DATA_CHUNK_LENGTH: usize = 300;
+
+// The parameters of Curve25519 as libgcrypt hashes them into keygrips
+// (see cipher/ecc-curves.c there).
+const CV25519_PARAMETERS: [(&str, &str); 5] = [
+    (
+        "p",
+        "7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFED",
+    ),
+    ("a", "01DB41"),
+    ("b", "01"),
+    (
+        "g",
+        "04\
+         0000000000000000000000000000000000000000000000000000000000000009\
+         20AE19A1B8A086B4E01EDD2C7748D14C923D4D7E6D7C61B229E9C5A27ECED3D9",
+    ),
+    (
+        "n",
+        "1000000000000000000000000000000014DEF9DEA2F79CD65812631A5CF5D3ED",
+    ),
+];
+
+fn crypto_error(err: impl std::fmt::Display) -> RadomskoError {
+    RadomskoError::CryptoError(err.to_string())
+}
+
+// Returns the socket of the running gpg-agent, starting one if need be
+// as gpg itself would.
+pub fn socket() -> Result<PathBuf, RadomskoError> {
+    let status = Exec::cmd("gpgconf")
+        .arg("--launch")
+        .arg("gpg-agent")
+        .join()
+        .map_err(popen_error_for("gpgconf"))?;
+    return_exit_status(status).map_err(|e| e.context("gpgconf --launch gpg-agent"))?;
+    let capture_data = Exec::cmd("gpgconf")
+        .arg("--list-dirs")
+        .arg("agent-socket")
+        .stdout(subprocess::Redirection::Pipe)
+        .capture()
+        .map_err(popen_error_for("gpgconf"))?;
+    return_exit_status(capture_data.exit_status)
+        .map_err(|e| e.context("gpgconf --list-dirs agent-socket"))?;
+    Ok(PathBuf::from(capture_data.stdout_str().trim_end()))
+}
+
+// Percent-escapes what Assuan does not allow verbatim in a line.
+fn escape(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for &byte in data {
+        match byte {
+            b'%' | b'\r' | b'\n' | b'+' | b' ' | 0..=0x1f | 0x7f.. => {
+                escaped.push_str(format!("%{:02X}", byte).as_str())
+            }
+            _ => escaped.push(byte as char),
+        }
+    }
+    escaped
+}
+
+// Undoes `escape()`. Data lines escape only what they must, so this
+// works on bytes.
+fn unescape(line: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(line.len());
+    let mut i = 0;
+    while i < line.len() {
+        let decoded = match line[i] {
+            b'%' => line
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(byte) => {
+                unescaped.push(byte);
+                i += 3;
+            }
+            None => {
+                unescaped.push(line[i]);
+                i += 1;
+            }
+        }
+    }
+    unescaped
+}
+
+// Explains an `ERR` line of the agent, which carries a libgpg-error
+// code (error source included) and its description.
+fn agent_error(line: &str) -> RadomskoError {
+    let (code, description) = line.split_once(' ').unwrap_or((line, ""));
+    match code.parse::<u32>().map(|value| value & 0xffff) {
+        Ok(GPG_ERR_CANCELED | GPG_ERR_FULLY_CANCELED) => RadomskoError::PinentryCancelled,
+        Ok(GPG_ERR_BAD_PASSPHRASE) => RadomskoError::BadPassphrase,
+        _ => RadomskoError::CryptoError(format!("gpg-agent: {}", description)),
+    }
+}
+
+// Appends `value` to `canonical` as a canonical S-expression string.
+fn push_string(canonical: &mut Vec<u8>, value: &[u8]) {
+    canonical.extend_from_slice(format!("{}:", value.len()).as_bytes());
+    canonical.extend_from_slice(value);
+}
+
+// Appends the list `(name value)` to `canonical`.
+fn push_parameter(canonical: &mut Vec<u8>, name: &str, value: &[u8]) {
+    canonical.push(b'(');
+    push_string(canonical, name.as_bytes());
+    push_string(canonical, value);
+    canonical.push(b')');
+}
+
+// Returns `mpi` as libgcrypt reads it: signed, so with a zero prepended
+// if its top bit is set.
+fn signed(mpi: &MPI) -> Vec<u8> {
+    let value = mpi.value();
+    match value.first() {
+        Some(msb) if msb & 0x80 != 0 => [&[0], value].concat(),
+        _ => value.to_vec(),
+    }
+}
+
+// Returns the keygrip by which gpg-agent knows the secret part of
+// `key`, a SHA-1 over its public parameters (see
+// `_gcry_pk_get_keygrip()` in libgcrypt). Only encryption keys gpg
+// generates are covered: RSA, ElGamal and ECDH over Curve25519.
+pub fn keygrip(key: &Key<PublicParts, UnspecifiedRole>) -> Result<String, RadomskoError> {
+    let mut hashed = Vec::new();
+    match key.mpis() {
+        PublicKey::RSA { n, .. } => hashed = signed(n),
+        PublicKey::ElGamal { p, g, y } => {
+            for (name, mpi) in [("p", p), ("g", g), ("y", y)] {
+                push_parameter(&mut hashed, name, signed(mpi).as_slice());
+            }
+        }
+        PublicKey::ECDH {
+            curve: Curve::Cv25519,
+            q,
+            ..
+        } => {
+            for (name, hex) in CV25519_PARAMETERS {
+                let value = data_encoding::HEXUPPER
+                    .decode(hex.as_bytes())
+                    .expect("curve parameters are valid hex");
+                push_parameter(&mut hashed, name, value.as_slice());
+            }
+            // The point is stored with a 0x40 prefix, which libgcrypt
+            // leaves out.
+            let q = q.value();
+            push_parameter(&mut hashed, "q", q.strip_prefix(&[0x40]).unwrap_or(q));
+        }
+        _ => {
+            return Err(RadomskoError::UnsupportedKey(format!(
+                "gpg-agent cannot be asked to decrypt with {} key {} \
+                 (only RSA, ElGamal and Curve25519 keys are supported)",
+                key.pk_algo(),
+                key.fingerprint()
+            )))
+        }
+    }
+    Ok(data_encoding::HEXUPPER.encode(Sha1::digest(hashed.as_slice()).as_slice()))
+}
+
+// Returns `ciphertext` as the S-expression the agent asks for when
+// decrypting.
+fn ciphertext_expression(ciphertext: &Ciphertext) -> Result<Vec<u8>, RadomskoError> {
+    let mut parameters = Vec::new();
+    let algorithm = match ciphertext {
+        Ciphertext::RSA { c } => {
+            push_parameter(&mut parameters, "a", c.value());
+            "rsa"
+        }
+        Ciphertext::ElGamal { e, c } => {
+            push_parameter(&mut parameters, "a", e.value());
+            push_parameter(&mut parameters, "b", c.value());
+            "elg"
+        }
+        Ciphertext::ECDH { e, key } => {
+            push_parameter(&mut parameters, "s", key);
+            push_parameter(&mut parameters, "e", e.value());
+            "ecdh"
+        }
+        _ => return Err(crypto_error("unsupported ciphertext")),
+    };
+    let mut expression = b"(".to_vec();
+    push_string(&mut expression, b"enc-val");
+    expression.push(b'(');
+    push_string(&mut expression, algorithm.as_bytes());
+    expression.extend_from_slice(parameters.as_slice());
+    expression.extend_from_slice(b"))");
+    Ok(expression)
+}
+
+// Returns the string in `(5:value<length>:<string>)`, the answer of
+// the agent to `PKDECRYPT`.
+fn decrypted_value(expression: &[u8]) -> Option<&[u8]> {
+    let rest = expression.strip_prefix(b"(5:value")?;
+    let colon = rest.iter().position(|&byte| byte == b':')?;
+    let length: usize = std::str::from_utf8(&rest[..colon]).ok()?.parse().ok()?;
+    let value = rest.get(colon + 1..colon + 1 + length)?;
+    match rest.get(colon + 1 + length) {
+        Some(b')') => Some(value),
+        _ => None,
+    }
+}
+
+// Strips the PKCS #1 v1.5 padding (`0 2 <nonzero bytes> 0`) off a
+// decrypted RSA or ElGamal session key. The leading zero may have been
+// lost along the way as an MPI.
+fn unpadded(value: &[u8]) -> Option<&[u8]> {
+    let value = value.strip_prefix(&[0]).unwrap_or(value);
+    let padded = value.strip_prefix(&[2])?;
+    let zero = padded.iter().position(|&byte| byte == 0)?;
+    Some(&padded[zero + 1..])
+}
+
+// What the agent answered to a command.
+#[derive(Default)]
+struct Response {
+    data: Vec<u8>,
+    // The keyword and arguments of each `S` line.
+    statuses: Vec<(String, String)>,
+}
+
+pub struct Agent {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Agent {
+    pub fn connect(socket: &Path) -> Result<Agent, RadomskoError> {
+        let writer = UnixStream::connect(socket).map_err(io_error_at("connect to", socket))?;
+        let mut agent = Agent {
+            reader: BufReader::new(writer.try_clone().map_err(io_error(TALK))?),
+            writer,
+        };
+        // Reads the greeting.
+        agent.response(None)?;
+        // Tells the pinentry where to prompt.
+        let ttyname = std::env::var("GPG_TTY").ok().or_else(|| {
+            // SAFETY: `ttyname()` returns null or a valid C string,
+            // which is copied before anything else might change it.
+            unsafe {
+                let name = libc::ttyname(libc::STDIN_FILENO);
+                (!name.is_null()).then(|| {
+                    std::ffi::CStr::from_ptr(name)
+                        .to_string_lossy()
+                        .into_owned()
+                })
+            }
+        });
+        if let Some(ttyname) = ttyname {
+            agent.transact(format!("OPTION ttyname={}", ttyname).as_str(), None)?;
+        }
+        if let Ok(term) = std::env::var("TERM") {
+            agent.transact(format!("OPTION ttytype={}", term).as_str(), None)?;
+        }
+        Ok(agent)
+    }
+
+    // Reads lines up to the `OK` or `ERR` that ends the response to a
+    // command, answering the `INQUIRE` for `inquiry.0` with `inquiry.1`
+    // and any other with nothing.
+    fn response(&mut self, inquiry: Option<(&str, &[u8])>) -> Result<Response, RadomskoError> {
+        let mut response = Response::default();
+        loop {
+            let mut line = Vec::new();
+            if self
+                .reader
+                .read_until(b'\n', &mut line)
+                .map_err(io_error(TALK))?
+                == 0
+            {
+                return Err(crypto_error("gpg-agent closed the connection"));
+            }
+            let line = line.strip_suffix(b"\n").unwrap_or(line.as_slice());
+            let (keyword, rest) = match line.iter().position(|&byte| byte == b' ') {
+                Some(space) => (&line[..space], &line[space + 1..]),
+                None => (line, &b""[..]),
+            };
+            if keyword == b"D" {
+                response.data.extend(unescape(rest));
+                continue;
+            }
+            let rest = String::from_utf8_lossy(rest);
+            match keyword {
+                b"OK" => return Ok(response),
+                b"ERR" => return Err(agent_error(&rest)),
+                b"S" => {
+                    let (status, arguments) = rest.split_once(' ').unwrap_or((&rest, ""));
+                    response
+                        .statuses
+                        .push((status.to_owned(), arguments.to_owned()));
+                }
+                b"INQUIRE" => {
+                    let asked = rest.split(' ').next().unwrap_or_default();
+                    if let Some((_, data)) = inquiry.filter(|(name, _)| *name == asked) {
+                        for chunk in data.chunks(DATA_CHUNK_LENGTH) {
+                            writeln!(self.writer, "D {}", escape(chunk)).map_err(io_error(TALK))?;
+                        }
+                    }
+                    writeln!(self.writer, "END").map_err(io_error(TALK))?;
+                }
+                // Comments and anything newer than this.
+                _ => (),
+            }
+        }
+    }
+
+    fn transact(
+        &mut self,
+        command: &str,
+        inquiry: Option<(&str, &[u8])>,
+    ) -> Result<Response, RadomskoError> {
+        writeln!(self.writer, "{}", command).map_err(io_error(TALK))?;
+        self.response(inquiry)
+    }
+
+    // Returns whether the agent holds the secret key with `keygrip`.
+    pub fn has_key(&mut self, keygrip: &str) -> Result<bool, RadomskoError> {
+        match self.transact(format!("HAVEKEY {}", keygrip).as_str(), None) {
+            Ok(_) => Ok(true),
+            Err(RadomskoError::CryptoError(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Returns a `Decryptor` using the agent's secret for `key`, known
+    // to it by `keygrip`, or `None` if it has none.
+    pub fn keypair_for(
+        &mut self,
+        key: &Key<PublicParts, UnspecifiedRole>,
+        keygrip: String,
+    ) -> Result<Option<AgentKeyPair<'_>>, RadomskoError> {
+        if !self.has_key(keygrip.as_str())? {
+            return Ok(None);
+        }
+        Ok(Some(AgentKeyPair {
+            agent: self,
+            public: key.clone(),
+            keygrip,
+            failure: None,
+        }))
+    }
+}
+
+// A key whose secret part stays in the agent.
+pub struct AgentKeyPair<'a> {
+    agent: &'a mut Agent,
+    public: Key<PublicParts, UnspecifiedRole>,
+    keygrip: String,
+    // Why decryption last failed, which `Decryptor::decrypt()` cannot
+    // pass on through `PKESK::decrypt()`.
+    pub failure: Option<RadomskoError>,
+}
+
+impl AgentKeyPair<'_> {
+    fn decrypt_session_key(
+        &mut self,
+        ciphertext: &Ciphertext,
+        plaintext_len: Option<usize>,
+    ) -> Result<SessionKey, RadomskoError> {
+        self.agent
+            .transact(format!("SETKEY {}", self.keygrip).as_str(), None)?;
+        let description = format!(
+            "Please enter the passphrase to unlock key {} to decrypt a password.",
+            self.public.fingerprint()
+        );
+        self.agent.transact(
+            format!("SETKEYDESC {}", escape(description.as_bytes())).as_str(),
+            None,
+        )?;
+        let response = self.agent.transact(
+            "PKDECRYPT",
+            Some(("CIPHERTEXT", ciphertext_expression(ciphertext)?.as_slice())),
+        )?;
+
+        // The agent ends its answer with a zero byte.
+        let data = response
+            .data
+            .strip_suffix(&[0])
+            .unwrap_or(response.data.as_slice());
+        let malformed = || crypto_error("malformed answer from gpg-agent");
+        let value = decrypted_value(data).ok_or_else(malformed)?;
+        let padded = !response
+            .statuses
+            .iter()
+            .any(|(status, arguments)| status == "PADDING" && arguments == "0");
+        match self.public.mpis() {
+            PublicKey::RSA { .. } | PublicKey::ElGamal { .. } => match padded {
+                true => Ok(unpadded(value).ok_or_else(malformed)?.to_vec().into()),
+                false => Ok(value.to_vec().into()),
+            },
+            // The agent only computes the shared point, from which the
+            // session key is then unwrapped.
+            PublicKey::ECDH { curve, .. } => {
+                let point = MPI::new(value);
+                let (shared, _) = point.decode_point(curve).map_err(crypto_error)?;
+                let shared: Protected = shared.to_vec().into();
+                openpgp::crypto::ecdh::decrypt_unwrap(
+                    &self.public,
+                    &shared,
+                    ciphertext,
+                    plaintext_len,
+                )
+                .map_err(crypto_error)
+            }
+            _ => Err(malformed()),
+        }
+    }
+}
+
+impl Decryptor for AgentKeyPair<'_> {
+    fn public(&self) -> &Key<PublicParts, UnspecifiedRole> {
+        &self.public
+    }
+
+    fn decrypt(
+        &mut self,
+        ciphertext: &Ciphertext,
+        plaintext_len: Option<usize>,
+    ) -> openpgp::Result<SessionKey> {
+        self.decrypt_session_key(ciphertext, plaintext_len)
+            .map_err(|e| {
+                let message = e.to_string();
+                self.failure = Some(e);
+                openpgp::anyhow::anyhow!(message)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_round_trip() {
+        let data = b"(5:value3:a%\n)\x00 +";
+        assert_eq!(escape(data), "(5:value3:a%25%0A)%00%20%2B");
+        assert_eq!(unescape(escape(data).as_bytes()), data);
+        assert_eq!(unescape(b"\xff%25"), b"\xff%");
+    }
+
+    #[test]
+    fn agent_errors() {
+        assert_eq!(
+            agent_error("83886179 Operation cancelled <Pinentry>"),
+            RadomskoError::PinentryCancelled
+        );
+        assert_eq!(
+            agent_error("67108875 Bad passphrase <Pinentry>"),
+            RadomskoError::BadPassphrase
+        );
+        assert_eq!(
+            agent_error("67108881 No secret key <GPG Agent>"),
+            RadomskoError::CryptoError("gpg-agent: No secret key <GPG Agent>".to_string())
+        );
+    }
+
+    #[test]
+    fn keygrip_rejects_unsupported_keys() {
+        let (cert, _) = openpgp::cert::CertBuilder::new()
+            .set_cipher_suite(openpgp::cert::CipherSuite::P256)
+            .add_storage_encryption_subkey()
+            .generate()
+            .unwrap();
+        let key = cert.keys().subkeys().next().unwrap().key().clone();
+        let err = keygrip(&key.role_into_unspecified()).unwrap_err();
+        assert!(matches!(err, RadomskoError::UnsupportedKey { .. }));
+    }
+
+    #[test]
+    fn decrypted_value_and_padding() {
+        assert_eq!(
+            decrypted_value(b"(5:value5:\x02\x01\x00ab)"),
+            Some(&b"\x02\x01\x00ab"[..])
+        );
+        assert_eq!(decrypted_value(b"(5:value9:ab)"), None);
+        assert_eq!(unpadded(b"\x02\x01\x07\x00ab"), Some(&b"ab"[..]));
+        assert_eq!(unpadded(b"\x00\x02\x01\x00ab"), Some(&b"ab"[..]));
+        assert_eq!(unpadded(b"\x01\x01\x00ab"), None);
+    }
+}
@@ -0,0 +1,241 @@
+// Interprets the machine-readable status lines that gpg writes when run
+// with `--status-fd`, so that its failures can be told apart.
+//
+// See doc/DETAILS in the GnuPG sources for the format.
+
+use crate::enums::RadomskoError;
+
+const STATUS_PREFIX: &str = "[GNUPG:] ";
+
+// The libgpg-error codes (sans error source) reported in `ERROR` and
+// `FAILURE` lines, and by gpg-agent.
+pub const GPG_ERR_BAD_PASSPHRASE: u32 = 11;
+pub const GPG_ERR_CANCELED: u32 = 99;
+// Reported instead of `GPG_ERR_CANCELED` when the pinentry is closed,
+// rather than cancelled, or once gpg gives up on asking.
+pub const GPG_ERR_FULLY_CANCELED: u32 = 198;
+
+// The output of gpg on its status file descriptor, separated from
+// whatever else it wrote there.
+pub struct GpgStatus {
+    // Each status line, split into its keyword and arguments.
+    lines: Vec<Vec<String>>,
+    // The human-readable remainder.
+    messages: String,
+}
+
+impl GpgStatus {
+    // Splits `output` (gpg's stderr, with the status lines mixed in).
+    pub fn parse(output: &str) -> GpgStatus {
+        let mut lines = Vec::new();
+        let mut messages = String::new();
+        for line in output.lines() {
+            match line.strip_prefix(STATUS_PREFIX) {
+                Some(status) => lines.push(status.split(' ').map(|s| s.to_owned()).collect()),
+                None => {
+                    messages.push_str(line);
+                    messages.push('\n');
+                }
+            }
+        }
+        GpgStatus { lines, messages }
+    }
+
+    // Returns the arguments of every status line with `keyword`.
+    fn arguments_of<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a [String]> {
+        self.lines
+            .iter()
+            .filter(move |line| line[0] == keyword)
+            .map(|line| &line[1..])
+    }
+
+    fn has(&self, keyword: &str) -> bool {
+        self.arguments_of(keyword).next().is_some()
+    }
+
+    // Returns whether an `ERROR` or `FAILURE` line carries `code`.
+    fn has_error_code(&self, code: u32) -> bool {
+        self.arguments_of("ERROR")
+            .chain(self.arguments_of("FAILURE"))
+            .filter_map(|args| args.get(1)?.parse::<u32>().ok())
+            .any(|value| value & 0xffff == code)
+    }
+
+    // Explains why decryption failed. Cancelling the pinentry and
+    // mistyping the passphrase are reported first, since gpg goes on
+    // to list the keys it lacks as well.
+    pub fn decrypt_error(&self) -> RadomskoError {
+        if self.has_error_code(GPG_ERR_CANCELED) || self.has_error_code(GPG_ERR_FULLY_CANCELED) {
+            return RadomskoError::PinentryCancelled;
+        }
+        if self.has("BAD_PASSPHRASE") || self.has_error_code(GPG_ERR_BAD_PASSPHRASE) {
+            return RadomskoError::BadPassphrase;
+        }
+        let missing: Vec<String> = self
+            .arguments_of("NO_SECKEY")
+            .filter_map(|args| args.first().cloned())
+            .collect();
+        if !missing.is_empty() {
+            return RadomskoError::NoSecretKey(missing);
+        }
+        // `NODATA` means that the file isn't OpenPGP data at all (it may
+        // be empty, say), after which gpg only reports the failure of
+        // the decrypt command.
+        let decrypt_failed = self
+            .arguments_of("FAILURE")
+            .any(|args| args.first().is_some_and(|location| location == "decrypt"));
+        if self.has("DECRYPTION_FAILED") || self.has("NODATA") || decrypt_failed {
+            return RadomskoError::DecryptionFailed(self.messages.trim().to_owned());
+        }
+        RadomskoError::SubprocessError(format!("failed to decrypt: ``{}''", self.messages.trim()))
+    }
+
+    // Explains why encryption failed.
+    pub fn encrypt_error(&self) -> RadomskoError {
+        if let Some(args) = self.arguments_of("INV_RECP").next() {
+            let reason = args.first().map(|code| invalid_recipient_reason(code));
+            return RadomskoError::InvalidRecipient(
+                args.get(1..).unwrap_or_default().join(" "),
+                reason.unwrap_or("no specific reason given").to_owned(),
+            );
+        }
+        RadomskoError::SubprocessError(format!("failed to encrypt: ``{}''", self.messages.trim()))
+    }
+}
+
+// Describes the reason code of an `INV_RECP` line.
+fn invalid_recipient_reason(code: &str) -> &'static str {
+    match code {
+        "1" => "not found",
+        "2" => "ambiguous specification",
+        "3" => "wrong key usage",
+        "4" => "key revoked",
+        "5" => "key expired",
+        "6" => "no CRL known",
+        "7" => "CRL too old",
+        "8" => "policy mismatch",
+        "9" => "not a secret key",
+        "10" => "key not trusted",
+        "11" => "missing certificate",
+        "12" => "missing issuer certificate",
+        "13" => "key disabled",
+        "14" => "syntax error in specification",
+        _ => "no specific reason given",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn parse_separates_messages() {
+        let status = GpgStatus::parse(indoc! {"
+            [GNUPG:] ENC_TO CE38821D83932F18 1 0
+            gpg: decryption failed: No secret key
+            [GNUPG:] END_DECRYPTION
+        "});
+        assert_eq!(status.lines.len(), 2);
+        assert_eq!(status.messages, "gpg: decryption failed: No secret key\n");
+    }
+
+    #[test]
+    fn decrypt_without_secret_key() {
+        let status = GpgStatus::parse(indoc! {"
+            [GNUPG:] ENC_TO CE38821D83932F18 1 0
+            gpg: encrypted with RSA key, ID CE38821D83932F18
+            [GNUPG:] NO_SECKEY CE38821D83932F18
+            [GNUPG:] BEGIN_DECRYPTION
+            [GNUPG:] DECRYPTION_FAILED
+            gpg: decryption failed: No secret key
+            [GNUPG:] END_DECRYPTION
+        "});
+        assert_eq!(
+            status.decrypt_error(),
+            RadomskoError::NoSecretKey(vec!["CE38821D83932F18".to_string()])
+        );
+    }
+
+    #[test]
+    fn decrypt_with_cancelled_pinentry() {
+        let status = GpgStatus::parse(indoc! {"
+            [GNUPG:] ENC_TO CE38821D83932F18 1 0
+            [GNUPG:] NO_SECKEY 5E361F3125A39CFC
+            gpg: public key decryption failed: Operation cancelled
+            [GNUPG:] ERROR pkdecrypt_failed 67108963
+            [GNUPG:] DECRYPTION_FAILED
+        "});
+        assert_eq!(status.decrypt_error(), RadomskoError::PinentryCancelled);
+
+        let status = GpgStatus::parse(indoc! {"
+            [GNUPG:] ENC_TO CE38821D83932F18 1 0
+            gpg: public key decryption failed: Operation cancelled
+            [GNUPG:] ERROR pkdecrypt_failed 67109062
+            [GNUPG:] DECRYPTION_FAILED
+        "});
+        assert_eq!(status.decrypt_error(), RadomskoError::PinentryCancelled);
+    }
+
+    #[test]
+    fn decrypt_with_bad_passphrase() {
+        for line in [
+            "[GNUPG:] ERROR pkdecrypt_failed 67108875",
+            "[GNUPG:] BAD_PASSPHRASE CE38821D83932F18",
+        ] {
+            let status =
+                GpgStatus::parse(format!("{}\n[GNUPG:] DECRYPTION_FAILED\n", line).as_str());
+            assert_eq!(status.decrypt_error(), RadomskoError::BadPassphrase);
+        }
+    }
+
+    #[test]
+    fn decrypt_failed_otherwise() {
+        let status = GpgStatus::parse(indoc! {"
+            [GNUPG:] DECRYPTION_FAILED
+            gpg: decryption failed: Bad session key
+        "});
+        assert_eq!(
+            status.decrypt_error(),
+            RadomskoError::DecryptionFailed("gpg: decryption failed: Bad session key".to_string())
+        );
+
+        // As for an empty file.
+        let status = GpgStatus::parse(indoc! {"
+            [GNUPG:] NODATA 2
+            [GNUPG:] FAILURE decrypt 4294967295
+            gpg: decrypt_message failed: Unknown system error
+        "});
+        assert_eq!(
+            status.decrypt_error(),
+            RadomskoError::DecryptionFailed(
+                "gpg: decrypt_message failed: Unknown system error".to_string()
+            )
+        );
+
+        let status = GpgStatus::parse(
+            "gpg: can't connect to the agent: IPC connect call failed
+",
+        );
+        assert!(matches!(
+            status.decrypt_error(),
+            RadomskoError::SubprocessError { .. }
+        ));
+    }
+
+    #[test]
+    fn encrypt_to_invalid_recipient() {
+        let status = GpgStatus::parse(indoc! {"
+            gpg: nobody@example.com: skipped: No name
+            [GNUPG:] INV_RECP 1 nobody@example.com
+            [GNUPG:] FAILURE encrypt 167772380
+        "});
+        assert_eq!(
+            status.encrypt_error(),
+            RadomskoError::InvalidRecipient(
+                "nobody@example.com".to_string(),
+                "not found".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,158 @@
+// Runs user-configured commands when things happen to entries.
+//
+// Hooks learn which entry is concerned through `RADOMSKO_ENTRY` and
+// which event fired through `RADOMSKO_HOOK`. They are never given the
+// secret itself.
+
+use subprocess::Exec;
+
+use crate::enums::{io_error, popen_error_for, RadomskoError};
+use crate::external_commands::return_exit_status;
+
+const ENTRY_ENV: &str = "RADOMSKO_ENTRY";
+const HOOK_ENV: &str = "RADOMSKO_HOOK";
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum HookEvent {
+    Copied,
+    Cleared,
+    Edited,
+    DecryptFailed,
+}
+
+impl HookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Copied => "after-copy",
+            HookEvent::Cleared => "after-clear",
+            HookEvent::Edited => "after-edit",
+            HookEvent::DecryptFailed => "after-decrypt-failure",
+        }
+    }
+}
+
+// The command (an argv, empty for none) to run for each event.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Hooks {
+    pub after_copy: Vec<String>,
+    pub after_clear: Vec<String>,
+    pub after_edit: Vec<String>,
+    pub after_decrypt_failure: Vec<String>,
+    // Seconds a hook may run before it is killed.
+    pub timeout: u64,
+}
+
+impl Hooks {
+    fn command_for(&self, event: HookEvent) -> &[String] {
+        match event {
+            HookEvent::Copied => &self.after_copy,
+            HookEvent::Cleared => &self.after_clear,
+            HookEvent::Edited => &self.after_edit,
+            HookEvent::DecryptFailed => &self.after_decrypt_failure,
+        }
+    }
+
+    // Runs the hook for `event` concerning `entry`, if one is set, and
+    // waits for it to finish.
+    pub fn run(&self, event: HookEvent, entry: &str) -> Result<(), RadomskoError> {
+        let (program, args) = match self.command_for(event).split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let mut process = Exec::cmd(program)
+            .args(args)
+            .env(ENTRY_ENV, entry)
+            .env(HOOK_ENV, event.name())
+            .stdin(subprocess::NullFile)
+            .popen()
+            .map_err(|e| popen_error_for(program)(e).context(format!("{} hook", event.name())))?;
+        match process.wait_timeout(std::time::Duration::from_secs(self.timeout))? {
+            Some(status) => return_exit_status(status).map_err(|e| match e {
+                RadomskoError::SubprocessError(reason) => {
+                    RadomskoError::SubprocessError(format!("{} hook: {}", event.name(), reason))
+                }
+                e => e,
+            }),
+            None => {
+                process
+                    .kill()
+                    .map_err(io_error(format!("kill the {} hook", event.name()).as_str()))?;
+                process.wait()?;
+                Err(RadomskoError::SubprocessError(format!(
+                    "{} hook timed out after {}s",
+                    event.name(),
+                    self.timeout
+                )))
+            }
+        }
+    }
+
+    // Like `run()`, but reports failure on stderr instead of returning
+    // it, for hooks that must not derail what triggered them. In the
+    // clipboard clearing helper, stderr is its log file.
+    pub fn run_and_report(&self, event: HookEvent, entry: &str) {
+        if let Err(e) = self.run(event, entry) {
+            eprintln!("Warning: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hooks_with_after_copy(script: &str, timeout: u64) -> Hooks {
+        Hooks {
+            after_copy: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            timeout,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unset_hook_does_nothing() {
+        let hooks = Hooks::default();
+        hooks.run(HookEvent::Edited, "a/b").unwrap();
+    }
+
+    #[test]
+    fn hook_gets_entry_and_event() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let output = tmp_dir.path().join("output");
+        let hooks = hooks_with_after_copy(
+            format!(
+                "echo \"$RADOMSKO_HOOK $RADOMSKO_ENTRY\" > '{}'",
+                output.display()
+            )
+            .as_str(),
+            5,
+        );
+        hooks.run(HookEvent::Copied, "web/example.com").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output).unwrap(),
+            "after-copy web/example.com\n"
+        );
+    }
+
+    #[test]
+    fn failing_hook_is_an_error() {
+        let hooks = hooks_with_after_copy("exit 3", 5);
+        let err = hooks.run(HookEvent::Copied, "a").unwrap_err();
+        assert_eq!(
+            err,
+            RadomskoError::SubprocessError(
+                "after-copy hook: subprocess failed with code 3".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn slow_hook_times_out() {
+        let hooks = hooks_with_after_copy("sleep 30", 0);
+        let err = hooks.run(HookEvent::Copied, "a").unwrap_err();
+        assert_eq!(
+            err,
+            RadomskoError::SubprocessError("after-copy hook timed out after 0s".to_string())
+        );
+    }
+}
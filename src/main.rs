@@ -1,49 +1,246 @@
+mod age_backend;
+mod clear_helper;
 mod cleartext_holder;
+mod clipboard;
+mod completion;
+mod config;
+mod crypto;
+mod entry;
 mod enums;
 mod external_commands;
+#[cfg(feature = "sequoia")]
+mod gpg_agent;
+mod gpg_status;
+mod hooks;
+mod otp;
+mod password_generator;
 mod password_store;
+mod picker;
+mod search;
+#[cfg(feature = "sequoia")]
+mod sequoia_backend;
 
-use std::io::Write;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
 use crate::cleartext_holder::CleartextHolderInterface;
-use crate::enums::RadomskoError;
+use crate::clipboard::Clipboard;
+use crate::config::Config;
+use crate::crypto::CryptoBackend;
+use crate::enums::ListingFormat;
 use crate::enums::ShowDestination;
+use crate::enums::ShowSelection;
+use crate::enums::{io_error, io_error_at, RadomskoError};
+use crate::hooks::HookEvent;
+use crate::password_generator::PasswordGenerator;
+use crate::password_store::EntryFormat;
 use crate::password_store::PasswordStoreInterface;
 
-const CLIPBOARD_CLEAR_TIMER: u64 = 13;
+const DEFAULT_GENERATED_PASSWORD_LENGTH: usize = 25;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 
 #[derive(clap::Parser)]
-#[command(name = "radomsko", version = clap::crate_version!(), about = "`pass` mimic")]
+#[command(
+    name = "radomsko",
+    version = clap::crate_version!(),
+    about = "`pass` mimic",
+    after_long_help = enums::EXIT_STATUS_HELP
+)]
 struct Cli {
+    #[arg(long, global = true, help = "read settings from this file")]
+    config: Option<std::path::PathBuf>,
+    #[arg(long, global = true, help = "use this password store")]
+    store_dir: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        help = "clear clipboard after"
+    )]
+    clip_timeout: Option<u64>,
     #[command(subcommand)]
     subcommand: Subcommand,
 }
 
 #[derive(clap::Subcommand)]
 enum Subcommand {
+    Completion(CompletionArgs),
+    Cp(TransferArgs),
     Edit(EditArgs),
     Find(FindArgs),
+    Generate(GenerateArgs),
+    Git(GitArgs),
+    Grep(GrepArgs),
+    Init(InitArgs),
+    Insert(InsertArgs),
+    Man(ManArgs),
+    Mv(TransferArgs),
+    Otp(OtpArgs),
+    Pick(PickArgs),
+    Rm(RmArgs),
     Show(ShowArgs),
 }
 
+#[derive(clap::Args)]
+struct CompletionArgs {
+    #[arg(help = "shell to complete for")]
+    shell: completion::Shell,
+}
+
 #[derive(clap::Args)]
 struct EditArgs {
-    #[arg(help = "target")]
+    #[arg(help = "target", add = completion::entry_completer())]
     target: std::path::PathBuf,
 }
 
 #[derive(clap::Args)]
 struct FindArgs {
-    #[arg(help = "keyword")]
-    keyword: std::path::PathBuf,
+    #[arg(required = true, help = "keywords")]
+    keywords: Vec<String>,
+    #[arg(short, long, help = "ignore case")]
+    ignore_case: bool,
+    #[arg(short = 'E', long, help = "interpret keywords as regular expressions")]
+    extended_regexp: bool,
+    #[arg(
+        short,
+        long,
+        conflicts_with = "extended_regexp",
+        help = "interpret keywords as globs"
+    )]
+    glob: bool,
+    #[arg(short, long, help = "list only entries matching every keyword")]
+    all: bool,
+    #[arg(long, value_enum, default_value_t = ListingFormat::Tree, help = "how to list entries")]
+    format: ListingFormat,
+}
+
+#[derive(clap::Args)]
+struct GenerateArgs {
+    #[arg(help = "target", add = completion::entry_completer())]
+    target: std::path::PathBuf,
+    #[arg(help = "password length", default_value_t = DEFAULT_GENERATED_PASSWORD_LENGTH)]
+    length: usize,
+    #[arg(short, long, help = "omit symbols")]
+    no_symbols: bool,
+    #[arg(short = 'a', long, help = "omit easily confused characters")]
+    no_ambiguous: bool,
+    #[arg(short, long, help = "use at least one character of each class")]
+    require_each_class: bool,
+    #[arg(short, long, help = "replace only the first line of an existing entry")]
+    in_place: bool,
+    #[arg(short, long, help = "overwrite existing entry")]
+    force: bool,
+    #[command(flatten)]
+    show_to: ShowWholeTo,
+}
+
+#[derive(clap::Args)]
+struct GitArgs {
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        help = "arguments to git"
+    )]
+    args: Vec<String>,
+}
+
+#[derive(clap::Args)]
+struct GrepArgs {
+    #[arg(help = "pattern")]
+    pattern: String,
+    #[arg(help = "(optional) subdirectory", add = completion::directory_completer())]
+    subdirectory: Option<std::path::PathBuf>,
+    #[arg(short, long, help = "ignore case")]
+    ignore_case: bool,
+    #[arg(short = 'E', long, help = "interpret pattern as a regular expression")]
+    extended_regexp: bool,
+}
+
+#[derive(clap::Args)]
+struct InitArgs {
+    #[arg(
+        short,
+        long,
+        help = "(optional) subdirectory to initialize",
+        add = completion::directory_completer()
+    )]
+    path: Option<std::path::PathBuf>,
+    #[arg(long, help = "write .age-recipients instead of .gpg-id")]
+    age: bool,
+    #[arg(required = true, help = "recipient gpg ids (or age public keys)")]
+    gpg_ids: Vec<String>,
+}
+
+#[derive(clap::Args)]
+struct InsertArgs {
+    #[arg(help = "target", add = completion::entry_completer())]
+    target: std::path::PathBuf,
+    #[arg(short, long, help = "read lines until EOF")]
+    multiline: bool,
+    #[arg(short, long, help = "overwrite existing entry")]
+    force: bool,
+}
+
+#[derive(clap::Args)]
+struct ManArgs {
+    #[arg(help = "directory to write man pages into")]
+    directory: std::path::PathBuf,
+}
+
+#[derive(clap::Args)]
+struct OtpArgs {
+    #[arg(help = "target", add = completion::entry_completer())]
+    target: std::path::PathBuf,
+    #[command(flatten)]
+    show_to: ShowWholeTo,
+}
+
+#[derive(clap::Args)]
+struct PickArgs {
+    #[arg(help = "(optional) initial query")]
+    query: Option<String>,
+    #[command(flatten)]
+    show_to: Option<ShowTo>,
+}
+
+#[derive(clap::Args)]
+struct RmArgs {
+    #[arg(help = "target", add = completion::entry_completer())]
+    target: std::path::PathBuf,
+    #[arg(short, long, help = "remove directories and their contents")]
+    recursive: bool,
+    #[arg(short, long, help = "do not prompt before removing")]
+    force: bool,
+}
+
+#[derive(clap::Args)]
+struct TransferArgs {
+    #[arg(help = "source", add = completion::entry_completer())]
+    source: std::path::PathBuf,
+    #[arg(help = "destination", add = completion::entry_completer())]
+    destination: std::path::PathBuf,
+    #[arg(short, long, help = "overwrite existing entries")]
+    force: bool,
 }
 
 #[derive(clap::Args)]
 struct ShowArgs {
-    #[arg(help = "(optional) target")]
+    #[arg(help = "(optional) target", add = completion::entry_completer())]
     target: Option<std::path::PathBuf>,
+    #[arg(short, long, help = "show only this line")]
+    line: Option<usize>,
+    #[arg(short, long, conflicts_with = "line", help = "show only this field")]
+    field: Option<String>,
+    #[arg(long, conflicts_with_all = ["line", "field"], help = "list field names")]
+    fields: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ListingFormat::Tree,
+        help = "how to list the entries of a directory"
+    )]
+    format: ListingFormat,
     #[command(flatten)]
     show_to: Option<ShowTo>,
 }
@@ -51,131 +248,1173 @@ struct ShowArgs {
 #[derive(clap::Args)]
 #[group(required = false, multiple = false)]
 struct ShowTo {
+    #[arg(
+        short,
+        num_args = 0..=1,
+        require_equals = true,
+        value_name = "LINE",
+        help = "copy to clipboard (first line, or LINE)"
+    )]
+    clip: Option<Option<usize>>,
+    #[arg(
+        short,
+        num_args = 0..=1,
+        require_equals = true,
+        value_name = "LINE",
+        help = "show QR code (first line, or LINE)"
+    )]
+    qrcode: Option<Option<usize>>,
+}
+
+// Like `ShowTo`, for subcommands that show a single line of their own
+// making (a generated password or a code) and so have no line to pick.
+#[derive(clap::Args)]
+#[group(required = false, multiple = false)]
+struct ShowWholeTo {
     #[arg(short, help = "copy to clipboard")]
     clip: bool,
     #[arg(short, help = "show QR code")]
     qrcode: bool,
 }
 
+// Notes what a transfer has changed so far, so that it can be undone
+// if it fails partway.
+#[derive(Default)]
+struct TransferJournal {
+    // Each path written, along with what it held before (if anything).
+    written: Vec<(PathBuf, Option<Vec<u8>>)>,
+    // The directories created, outermost first.
+    created: Vec<PathBuf>,
+}
+
+impl TransferJournal {
+    // Notes the current contents of `path`, which is about to be
+    // written.
+    fn record_write(&mut self, path: &Path) -> Result<(), RadomskoError> {
+        if self.written.iter().any(|(written, _)| written == path) {
+            return Ok(());
+        }
+        let previous = match path.is_file() {
+            true => Some(std::fs::read(path).map_err(io_error_at("read", path))?),
+            false => None,
+        };
+        self.written.push((path.to_path_buf(), previous));
+        Ok(())
+    }
+
+    // Puts back what was there before, on a best-effort basis: the
+    // transfer has already failed, and this mustn't mask why.
+    fn roll_back(&self) {
+        for (path, previous) in self.written.iter().rev() {
+            let _ = match previous {
+                Some(contents) => std::fs::write(path, contents),
+                None if path.exists() => std::fs::remove_file(path),
+                None => Ok(()),
+            };
+        }
+        for directory in self.created.iter().rev() {
+            let _ = std::fs::remove_dir(directory);
+        }
+    }
+}
+
 struct CommandRunner {
+    config: Config,
+    clipboard: Clipboard,
     password_store: PasswordStoreInterface,
+    openpgp: Box<dyn CryptoBackend>,
+    age: Box<dyn CryptoBackend>,
 }
 
-fn wait_and_clear_clipboard(target: &str) {
-    println!(
-        "Clipped ``{};'' clearing in {}s",
-        target, CLIPBOARD_CLEAR_TIMER
-    );
-    ctrlc::set_handler(move || {
-        eprintln!("Interrupted");
-        external_commands::clear_clipboard().expect("Error clearing clipboard");
-        std::process::exit(1);
+// Returns the destination picked by `show_to` along with the line
+// number given to it, if any.
+fn show_destination(show_to: Option<&ShowTo>) -> (ShowDestination, Option<usize>) {
+    match show_to {
+        Some(show_to) => {
+            if let Some(line) = show_to.clip {
+                (ShowDestination::Clip, line)
+            } else if let Some(line) = show_to.qrcode {
+                (ShowDestination::QrCode, line)
+            } else {
+                panic!("BUG: unhandled `ShowTo` arm")
+            }
+        }
+        None => (ShowDestination::Stdout, None),
+    }
+}
+
+// Returns the destination picked by `show_to`.
+fn whole_show_destination(show_to: &ShowWholeTo) -> ShowDestination {
+    if show_to.clip {
+        ShowDestination::Clip
+    } else if show_to.qrcode {
+        ShowDestination::QrCode
+    } else {
+        ShowDestination::Stdout
+    }
+}
+
+// Returns the part of a password picked by the arguments to `show`,
+// where `line` is the one given to `-c` or `-q`. Clap cannot see that
+// such a line conflicts with `--field` and `--fields`, so this checks.
+fn show_selection(args: &ShowArgs, line: Option<usize>) -> Result<ShowSelection, clap::Error> {
+    if line.is_some() && (args.field.is_some() || args.fields) {
+        let mut command = Cli::command();
+        command.build();
+        return Err(command.find_subcommand_mut("show").unwrap().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "a line for -c or -q cannot be used with '--field' or '--fields'",
+        ));
+    }
+    Ok(if let Some(line) = line.or(args.line) {
+        ShowSelection::Line(line)
+    } else if let Some(field) = args.field.clone() {
+        ShowSelection::Field(field)
+    } else if args.fields {
+        ShowSelection::FieldNames
+    } else {
+        ShowSelection::Default
     })
-    .expect("Error setting signal handler");
-    std::thread::sleep(std::time::Duration::from_secs(CLIPBOARD_CLEAR_TIMER));
-    external_commands::clear_clipboard().expect("Error clearing clipboard");
+}
+
+// Returns the index of the subcommand in `args`, skipping over the
+// global options of `command` (and their values) that precede it.
+fn subcommand_index(command: &clap::Command, args: &[String]) -> Option<usize> {
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        let long = match arg.strip_prefix("--") {
+            Some(long) => long,
+            None if arg.starts_with('-') => {
+                index += 1;
+                continue;
+            }
+            None => return Some(index),
+        };
+        let takes_value = command
+            .get_arguments()
+            .any(|option| option.get_long() == Some(long) && option.get_action().takes_values());
+        index += if takes_value { 2 } else { 1 };
+    }
+    None
+}
+
+// `pass` spells a line number for `-c` and `-q` as `-c2`, which clap
+// would read as `-c -2`. Rewrites such arguments to the subcommands
+// that take them (those flattening `ShowTo`) as `-c=2`.
+fn with_attached_line_numbers(args: Vec<String>) -> Vec<String> {
+    let command = Cli::command();
+    let index = match subcommand_index(&command, &args) {
+        Some(index) => index,
+        None => return args,
+    };
+    let takes_line = command
+        .find_subcommand(args[index].as_str())
+        .is_some_and(|subcommand| {
+            subcommand
+                .get_arguments()
+                .any(|arg| arg.get_id() == "clip" && arg.get_action().takes_values())
+        });
+    if !takes_line {
+        return args;
+    }
+    let mut past_separator = false;
+    args.into_iter()
+        .enumerate()
+        .map(|(position, arg)| {
+            past_separator = past_separator || arg == "--";
+            let is_attached = position > index
+                && !past_separator
+                && (arg.starts_with("-c") || arg.starts_with("-q"))
+                && arg.len() > 2
+                && arg[2..].chars().all(|c| c.is_ascii_digit());
+            match is_attached {
+                true => format!("{}={}", &arg[..2], &arg[2..]),
+                false => arg,
+            }
+        })
+        .collect()
+}
+
+// Reads the cleartext of a new password named `target`.
+//
+// *    In multiline mode, reads everything until EOF.
+// *    Otherwise, prompts twice without echo if stdin is a terminal
+//      and reads a single line if it is not.
+fn read_new_password(target: &str, multiline: bool) -> Result<String, RadomskoError> {
+    let stdin = std::io::stdin();
+    if multiline {
+        if stdin.is_terminal() {
+            eprintln!(
+                "Enter contents of ``{}'' and press Ctrl-D when done:",
+                target
+            );
+        }
+        let mut contents = String::new();
+        stdin
+            .lock()
+            .read_to_string(&mut contents)
+            .map_err(io_error("read standard input"))?;
+        return Ok(contents);
+    }
+
+    if !stdin.is_terminal() {
+        let mut line = String::new();
+        stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(io_error("read standard input"))?;
+        return Ok(format!("{}\n", line.trim_end_matches(['\r', '\n'])));
+    }
+
+    let password = rpassword::prompt_password(format!("Enter password for ``{}'': ", target))
+        .map_err(io_error("read the password"))?;
+    let confirmation = rpassword::prompt_password(format!("Retype password for ``{}'': ", target))
+        .map_err(io_error("read the password"))?;
+    if password != confirmation {
+        return Err(RadomskoError::BadInput(
+            "passwords do not match".to_string(),
+        ));
+    }
+    Ok(format!("{}\n", password))
+}
+
+// Asks the user a yes-or-no `question` on the terminal.
+fn confirm(question: &str) -> Result<bool, RadomskoError> {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(io_error("read the answer"))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
 
 impl CommandRunner {
-    pub fn new() -> Result<CommandRunner, RadomskoError> {
+    pub fn new(config: Config) -> Result<CommandRunner, RadomskoError> {
+        let mut password_store = PasswordStoreInterface::new(
+            config.store_dir.as_str(),
+            std::io::stdout().is_terminal(),
+        )?;
+        if let Some(key) = &config.key {
+            password_store = password_store.with_recipients_override(key.clone());
+        }
         Ok(CommandRunner {
-            password_store: PasswordStoreInterface::new("", true)?,
+            openpgp: crypto::backend_for(&config)?,
+            age: Box::new(age_backend::AgeBackend::new(&config.age_identities)),
+            clipboard: Clipboard::new(
+                config.clipboard_backend,
+                config.clipboard_command.as_deref(),
+                config.clipboard_paste_once,
+                config.clipboard_sensitive,
+            ),
+            config,
+            password_store,
         })
     }
 
-    fn get_encrypted_edited_password(&self, target: &str) -> Result<Vec<u8>, RadomskoError> {
-        let cleartext_holder = CleartextHolderInterface::new("")?;
+    fn backend_for(&self, format: EntryFormat) -> &dyn CryptoBackend {
+        match format {
+            EntryFormat::Gpg => self.openpgp.as_ref(),
+            EntryFormat::Age => self.age.as_ref(),
+        }
+    }
+
+    fn decrypt(&self, password: &Path) -> Result<String, RadomskoError> {
+        self.backend_for(EntryFormat::of(password))
+            .decrypt(password)
+            .map_err(|e| {
+                let name = self.password_store.symbolic_name_for(password);
+                self.config
+                    .hooks
+                    .run_and_report(HookEvent::DecryptFailed, name.to_str().unwrap());
+                e.context(format!("cannot decrypt ``{}''", name.display()))
+            })
+    }
+
+    fn encrypt(
+        &self,
+        cleartext: &str,
+        format: EntryFormat,
+        recipients: &[String],
+    ) -> Result<Vec<u8>, RadomskoError> {
+        self.backend_for(format).encrypt(cleartext, recipients)
+    }
+
+    fn get_edited_cleartext(&self, target: &str) -> Result<String, RadomskoError> {
+        let cleartext_holder = CleartextHolderInterface::new(self.config.cleartext_dir.as_str())?;
         let target_path = self.password_store.path_for(target)?;
         let mut cleartext_tempfile = cleartext_holder.new_entry()?;
 
         let password_exists = target_path.is_file();
         if password_exists {
-            let cleartext_password =
-                external_commands::decrypt_password_to_string(target_path.as_path())?;
-            cleartext_tempfile
-                .as_file_mut()
-                .write_all(cleartext_password.as_bytes())?;
-            cleartext_tempfile.as_file_mut().sync_data()?;
+            let cleartext_password = self.decrypt(target_path.as_path())?;
+            let cleartext_path = cleartext_tempfile.path().to_path_buf();
+            let file = cleartext_tempfile.as_file_mut();
+            file.write_all(cleartext_password.as_bytes())
+                .and_then(|_| file.sync_data())
+                .map_err(io_error_at("write", &cleartext_path))?;
         }
 
+        // Fail before the user puts any work into editing.
+        self.password_store.required_recipients_for(&target_path)?;
         external_commands::invoke_editor(cleartext_tempfile.path())?;
-        external_commands::encrypt_cleartext(cleartext_tempfile.path())?;
-        let encrypted =
-            CleartextHolderInterface::encrypted_contents_for(cleartext_tempfile.path())?;
-        cleartext_holder.remove_encrypted_output_of(cleartext_tempfile.path())?;
+        std::fs::read_to_string(cleartext_tempfile.path())
+            .map_err(io_error_at("read", cleartext_tempfile.path()))
+    }
+
+    // Encrypts `cleartext` for the recipients of `path` and writes it
+    // there. If those recipients call for another format, the password
+    // is written under the matching extension and `path` is removed.
+    //
+    // Returns the paths changed.
+    fn write_encrypted_to(
+        &self,
+        path: &Path,
+        cleartext: &str,
+    ) -> Result<Vec<PathBuf>, RadomskoError> {
+        let format = self.password_store.format_for(path);
+        let recipients = self.password_store.required_recipients_for(path)?;
+        let encrypted = self.encrypt_for(path, cleartext, format, &recipients)?;
+        self.write_encrypted_bytes_to(path, format, &encrypted)
+    }
 
-        Ok(encrypted)
+    // Encrypts `cleartext` for the password at `path`, in `format` and
+    // to `recipients`.
+    fn encrypt_for(
+        &self,
+        path: &Path,
+        cleartext: &str,
+        format: EntryFormat,
+        recipients: &[String],
+    ) -> Result<Vec<u8>, RadomskoError> {
+        self.encrypt(cleartext, format, recipients).map_err(|e| {
+            let name = self.password_store.symbolic_name_for(path);
+            e.context(format!("cannot encrypt ``{}''", name.display()))
+        })
+    }
+
+    // Writes the `encrypted` password to `path` under the extension of
+    // `format`, removing `path` if that differs from its own.
+    //
+    // Returns the paths changed.
+    fn write_encrypted_bytes_to(
+        &self,
+        path: &Path,
+        format: EntryFormat,
+        encrypted: &[u8],
+    ) -> Result<Vec<PathBuf>, RadomskoError> {
+        let written = path.with_extension(format.extension());
+        std::fs::write(&written, encrypted).map_err(io_error_at("write", &written))?;
+        if written == path || !path.exists() {
+            return Ok(vec![written]);
+        }
+        std::fs::remove_file(path).map_err(io_error_at("remove", path))?;
+        Ok(vec![written, path.to_path_buf()])
+    }
+
+    // Encrypts `cleartext` and writes it into the password store under
+    // the name `target`.
+    //
+    // Returns the paths changed.
+    fn write_encrypted(
+        &self,
+        target: &str,
+        cleartext: &str,
+    ) -> Result<Vec<PathBuf>, RadomskoError> {
+        self.write_encrypted_to(&self.password_store.path_for(target)?, cleartext)
+    }
+
+    // Commits the changes to `paths` if the password store is a git
+    // repository.
+    fn commit(&self, paths: &[PathBuf], message: &str) -> Result<(), RadomskoError> {
+        let root = self.password_store.root();
+        if !external_commands::is_git_repository(root) {
+            return Ok(());
+        }
+        external_commands::git_commit(root, paths, message)
+    }
+
+    // Sends `cleartext` from `target` to `dest`. The clipboard gets its
+    // previous contents back once the timer runs out, which a detached
+    // helper takes care of.
+    fn show_cleartext(
+        &self,
+        target: &str,
+        cleartext: &str,
+        dest: ShowDestination,
+    ) -> Result<(), RadomskoError> {
+        if dest != ShowDestination::Clip {
+            return external_commands::show_cleartext(
+                cleartext,
+                dest,
+                &self.clipboard,
+                &self.config,
+            );
+        }
+        let pid_file = clear_helper::pid_file()?;
+        clear_helper::cancel_pending_clear(&pid_file)?;
+        let previous = self.clipboard.paste().unwrap_or(None);
+        external_commands::show_cleartext(cleartext, dest, &self.clipboard, &self.config)?;
+        self.config.hooks.run_and_report(HookEvent::Copied, target);
+        println!(
+            "Clipped ``{};'' clearing in {}s",
+            target, self.config.clip_timeout
+        );
+        clear_helper::spawn_clear(
+            &pid_file,
+            clear_helper::PendingClear {
+                entry: target.to_owned(),
+                clipboard: self.clipboard.clone(),
+                secret: cleartext.trim().to_owned(),
+                previous,
+                hooks: self.config.hooks.clone(),
+            },
+            self.config.clip_timeout,
+        )
     }
 
     pub fn edit(&self, target: &str) -> Result<(), RadomskoError> {
-        let encrypted = self.get_encrypted_edited_password(target)?;
+        let cleartext = self.get_edited_cleartext(target)?;
+        let changed = self.write_encrypted(target, cleartext.as_str())?;
+        self.commit(
+            &changed,
+            format!("Edit password for {} using radomsko", target).as_str(),
+        )?;
+        self.config.hooks.run_and_report(HookEvent::Edited, target);
+        Ok(())
+    }
+
+    pub fn insert(&self, target: &str, multiline: bool, force: bool) -> Result<(), RadomskoError> {
+        let target_path = self.password_store.path_for(target)?;
+        if target_path.exists() && !force {
+            return Err(RadomskoError::AlreadyExists(target.to_string()));
+        }
 
+        let cleartext = read_new_password(target, multiline)?;
+        self.insert_cleartext(target, cleartext.as_str())
+    }
+
+    // Aids `insert()` by storing the `cleartext` read for `target`.
+    fn insert_cleartext(&self, target: &str, cleartext: &str) -> Result<(), RadomskoError> {
+        if cleartext.trim().is_empty() {
+            return Err(RadomskoError::BadInput("empty password".to_string()));
+        }
+        let changed = self.write_encrypted(target, cleartext)?;
+        self.commit(
+            &changed,
+            format!("Add given password for {} to store", target).as_str(),
+        )
+    }
+
+    pub fn generate(
+        &self,
+        target: &str,
+        generator: &PasswordGenerator,
+        length: usize,
+        in_place: bool,
+        force: bool,
+        dest: ShowDestination,
+    ) -> Result<(), RadomskoError> {
         let target_path = self.password_store.path_for(target)?;
-        Ok(std::fs::write(target_path, encrypted)?)
+        let password_exists = target_path.is_file();
+        if in_place && !password_exists {
+            return Err(RadomskoError::NotFound(target.to_string()));
+        } else if password_exists && !in_place && !force {
+            return Err(RadomskoError::AlreadyExists(target.to_string()));
+        }
+
+        let password = generator.generate(length)?;
+        let cleartext = if in_place {
+            let existing = self.decrypt(target_path.as_path())?;
+            match existing.split_once('\n') {
+                Some((_, rest)) => format!("{}\n{}", password, rest),
+                None => format!("{}\n", password),
+            }
+        } else {
+            format!("{}\n", password)
+        };
+        let changed = self.write_encrypted(target, cleartext.as_str())?;
+        let verb = match in_place {
+            true => "Replace",
+            false => "Add",
+        };
+        self.commit(
+            &changed,
+            format!("{} generated password for {}", verb, target).as_str(),
+        )?;
+
+        self.show_cleartext(target, password.as_str(), dest)
+    }
+
+    pub fn rm(&self, target: &str, recursive: bool, force: bool) -> Result<(), RadomskoError> {
+        // Fails before asking for anything that can't be removed.
+        self.password_store.removal_target(target, recursive)?;
+        if !force && !confirm(format!("Really remove ``{}''?", target).as_str())? {
+            return Ok(());
+        }
+        let removed = self.password_store.remove(target, recursive)?;
+        println!("Removed ``{}''", removed.display());
+        self.commit(&[removed], format!("Remove {} from store", target).as_str())
+    }
+
+    // Sets the recipients of `subdirectory` and re-encrypts the
+    // passwords they govern. Every password is re-encrypted in memory
+    // before anything is written, so that a failure (e.g. a cancelled
+    // pinentry) leaves the store as it was. Passwords already in
+    // `format` and encrypted to `recipients` are left alone.
+    pub fn init(
+        &self,
+        subdirectory: &str,
+        format: EntryFormat,
+        recipients: &[String],
+    ) -> Result<(), RadomskoError> {
+        let effective = self.password_store.effective_recipients(format, recipients);
+        let mut reencrypted: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+        for password in self.password_store.governed_by(subdirectory)? {
+            if EntryFormat::of(&password) == format
+                && self.password_store.recipients_for(&password)?.as_ref() == Some(&effective)
+            {
+                continue;
+            }
+            let cleartext = self.decrypt(&password)?;
+            let encrypted = self.encrypt_for(&password, cleartext.as_str(), format, &effective)?;
+            reencrypted.push((password, encrypted));
+        }
+
+        self.password_store
+            .set_recipients(subdirectory, format, recipients)?;
+        match subdirectory.is_empty() {
+            true => println!("Password store initialized for {}", recipients.join(", ")),
+            false => println!(
+                "Password store initialized for {} (``{}'')",
+                recipients.join(", "),
+                subdirectory
+            ),
+        }
+        for (password, encrypted) in reencrypted.iter() {
+            self.write_encrypted_bytes_to(password, format, encrypted)?;
+        }
+        if !reencrypted.is_empty() {
+            println!("Re-encrypted {} password(s)", reencrypted.len());
+        }
+        let message = match format {
+            EntryFormat::Gpg => format!("Set GPG id to {}", recipients.join(", ")),
+            EntryFormat::Age => format!("Set age recipients to {}", recipients.join(", ")),
+        };
+        self.commit(
+            &[self.password_store.root().join(subdirectory)],
+            message.as_str(),
+        )
+    }
+
+    // Copies (or, if `remove_source` is set, moves) `source` to
+    // `destination`, re-encrypting any password whose recipients differ
+    // between the two locations. If any password fails to transfer,
+    // those already written are undone.
+    fn transfer(
+        &self,
+        source: &str,
+        destination: &str,
+        force: bool,
+        remove_source: bool,
+    ) -> Result<(), RadomskoError> {
+        let plan = self.password_store.plan_transfer(source, destination)?;
+        if !force {
+            if let Some((_, to)) = plan.iter().find(|(_, to)| to.exists()) {
+                let name = self.password_store.symbolic_name_for(to);
+                return Err(RadomskoError::AlreadyExists(name.display().to_string()));
+            }
+        }
+
+        let mut journal = TransferJournal::default();
+        let mut changed = match self.transfer_planned(&plan, &mut journal) {
+            Ok(changed) => changed,
+            Err(e) => {
+                journal.roll_back();
+                return Err(e);
+            }
+        };
+
+        if remove_source {
+            for (from, _) in plan.iter() {
+                std::fs::remove_file(from).map_err(io_error_at("remove", from))?;
+                self.password_store.prune_empty_directories_above(from)?;
+            }
+        }
+
+        if remove_source {
+            changed.extend(plan.into_iter().map(|(from, _)| from));
+        }
+        let message = match remove_source {
+            true => format!("Rename {} to {}", source, destination),
+            false => format!("Copy {} to {}", source, destination),
+        };
+        self.commit(&changed, message.as_str())
+    }
+
+    // Aids `transfer()` by carrying out the `plan`, noting each change
+    // in `journal`.
+    //
+    // Returns the paths changed.
+    fn transfer_planned(
+        &self,
+        plan: &[(PathBuf, PathBuf)],
+        journal: &mut TransferJournal,
+    ) -> Result<Vec<PathBuf>, RadomskoError> {
+        let mut changed: Vec<PathBuf> = Vec::new();
+        for (from, to) in plan.iter() {
+            journal
+                .created
+                .extend(self.password_store.create_directories_for(to)?);
+            if !password_store::is_password_file(from) {
+                journal.record_write(to)?;
+                std::fs::copy(from, to).map_err(io_error_at("copy", from))?;
+                changed.push(to.clone());
+                continue;
+            }
+            // `write_encrypted_to()` may swap the extension of `to`.
+            for format in [EntryFormat::Gpg, EntryFormat::Age] {
+                journal.record_write(&to.with_extension(format.extension()))?;
+            }
+            // Recipients files are copied first, so this sees the ones
+            // that will govern `to`.
+            let format = self.password_store.format_for(to);
+            let from_recipients = self.password_store.recipients_for(from)?;
+            let to_recipients = self.password_store.required_recipients_for(to)?;
+            if EntryFormat::of(from) == format
+                && EntryFormat::of(to) == format
+                && from_recipients.as_ref() == Some(&to_recipients)
+            {
+                std::fs::copy(from, to).map_err(io_error_at("copy", from))?;
+                changed.push(to.clone());
+            } else {
+                let cleartext = self.decrypt(from)?;
+                changed.extend(self.write_encrypted_to(to, cleartext.as_str())?);
+            }
+        }
+        Ok(changed)
+    }
+
+    pub fn cp(&self, source: &str, destination: &str, force: bool) -> Result<(), RadomskoError> {
+        self.transfer(source, destination, force, false)
+    }
+
+    pub fn mv(&self, source: &str, destination: &str, force: bool) -> Result<(), RadomskoError> {
+        self.transfer(source, destination, force, true)
+    }
+
+    pub fn git(&self, args: &[String]) -> Result<(), RadomskoError> {
+        external_commands::git_passthrough(self.password_store.root(), args)
     }
 
-    pub fn find(&self, search_term: &str) -> Result<(), RadomskoError> {
-        Ok(println!(
-            "{}",
-            self.password_store.draw_tree("", search_term)?
-        ))
+    pub fn find(
+        &self,
+        search: &search::SearchTerms,
+        format: ListingFormat,
+    ) -> Result<(), RadomskoError> {
+        print!("{}", self.password_store.list("", Some(search), format)?);
+        Ok(())
+    }
+
+    pub fn grep(&self, pattern: &regex::Regex, subdirectory: &str) -> Result<(), RadomskoError> {
+        let colorize = self.password_store.colorize_display();
+        for password in self.password_store.passwords_under(subdirectory)? {
+            let name = self.password_store.symbolic_name_for(&password);
+            let cleartext = match self.decrypt(&password) {
+                Ok(cleartext) => cleartext,
+                Err(e) => {
+                    eprintln!("Skipping ``{}'': {}", name.display(), e.root_cause());
+                    continue;
+                }
+            };
+
+            let lines = search::matching_lines(cleartext.as_str(), pattern);
+            if lines.is_empty() {
+                continue;
+            }
+            let heading = format!("{}:", name.display());
+            match colorize {
+                true => println!("{}", password_store::highlight(heading.as_str())),
+                false => println!("{}", heading),
+            }
+            for line in lines {
+                match colorize {
+                    true => println!("{}", search::highlight_matches(line, pattern)),
+                    false => println!("{}", line),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Computes the one-time password for the `otpauth://` URI in
+    // `target` and sends it to `dest`. HOTP counters are advanced and
+    // saved back to `target` before the code is shown.
+    pub fn otp(&self, target: &str, dest: ShowDestination) -> Result<(), RadomskoError> {
+        let path = self.password_store.path_for(target)?;
+        if !path.is_file() {
+            return Err(RadomskoError::NotFound(target.to_string()));
+        }
+        let cleartext = self.decrypt(path.as_path())?;
+        let (span, otp_auth) = otp::OtpAuth::find_in(cleartext.as_str())?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let code = otp_auth.code(now);
+
+        if let otp::OtpKind::Hotp { .. } = otp_auth.kind {
+            // Only the URI changes; the rest keeps its exact bytes.
+            let mut updated = cleartext.clone();
+            updated.replace_range(
+                span.clone(),
+                otp::with_incremented_counter(&cleartext[span])?.as_str(),
+            );
+            let changed = self.write_encrypted(target, updated.as_str())?;
+            self.commit(
+                &changed,
+                format!("Increment HOTP counter for {}", target).as_str(),
+            )?;
+        }
+
+        self.show_cleartext(target, code.as_str(), dest)
+    }
+
+    // Lets the user pick a password interactively, starting from
+    // `query`, and shows it as `show()` would. Does nothing if they
+    // back out.
+    pub fn pick(
+        &self,
+        query: &str,
+        dest: ShowDestination,
+        selection: ShowSelection,
+    ) -> Result<(), RadomskoError> {
+        let names: Vec<String> = self
+            .password_store
+            .passwords_under("")?
+            .iter()
+            .map(|password| {
+                let name = self.password_store.symbolic_name_for(password);
+                name.to_str().unwrap().to_owned()
+            })
+            .collect();
+        if names.is_empty() {
+            return Err(RadomskoError::BadInput(
+                "the password store is empty".to_string(),
+            ));
+        }
+        match picker::pick(&names, query)? {
+            Some(target) => self.show(target.as_str(), dest, selection, ListingFormat::Tree),
+            None => Ok(()),
+        }
     }
 
-    pub fn show(&self, target: &str, dest: ShowDestination) -> Result<(), RadomskoError> {
+    // Shows the part of the password `target` picked by `selection` at
+    // `dest`, or lists the passwords in `format` if `target` is a
+    // directory.
+    pub fn show(
+        &self,
+        target: &str,
+        dest: ShowDestination,
+        selection: ShowSelection,
+        format: ListingFormat,
+    ) -> Result<(), RadomskoError> {
         // If a tree can be drawn at all (i.e. `target` is ambiguous),
         // then we leave it at that.
-        if let Ok(render) = self.password_store.draw_tree(target, "") {
-            println!("{}", render);
+        if let Ok(render) = self.password_store.list(target, None, format) {
+            print!("{}", render);
             return Ok(());
         }
 
         let path = self.password_store.path_for(target)?;
         if !path.is_file() {
-            return Err(RadomskoError::NotFound);
-        }
-        external_commands::decrypt_password(path.as_path(), dest)?;
-        if dest == ShowDestination::Clip {
-            let _ = external_commands::switch_workspace();
-            wait_and_clear_clipboard(target);
+            return Err(RadomskoError::NotFound(target.to_string()));
         }
-        Ok(())
+        let cleartext = self.decrypt(path.as_path())?;
+        let parsed = entry::Entry::parse(cleartext.as_str());
+        let field_names: String;
+        let shown = match selection {
+            ShowSelection::Default => match dest {
+                ShowDestination::Stdout => cleartext.as_str(),
+                _ => parsed.password(),
+            },
+            ShowSelection::Line(line) => entry::line_of(cleartext.as_str(), line)?,
+            ShowSelection::Field(key) => match parsed.field(key.as_str()) {
+                Some(value) => value,
+                None => {
+                    return Err(RadomskoError::BadInput(format!(
+                        "``{}'' has no field ``{}''",
+                        target, key
+                    )))
+                }
+            },
+            ShowSelection::FieldNames => {
+                field_names = parsed.field_names().join("\n");
+                field_names.as_str()
+            }
+        };
+        self.show_cleartext(target, shown, dest)
     }
 }
 
 pub fn main_impl() -> Result<(), RadomskoError> {
-    let command_runner = CommandRunner::new()?;
-    let cli = Cli::parse();
+    let cli = Cli::parse_from(with_attached_line_numbers(std::env::args().collect()));
     match cli.subcommand {
+        Subcommand::Completion(args) => {
+            return completion::write_registration(&Cli::command(), args.shell)
+        }
+        Subcommand::Man(args) => {
+            return completion::write_man_pages(Cli::command(), &args.directory)
+        }
+        _ => (),
+    }
+    let config = Config::load(config::CommandLineOverrides {
+        config: cli.config,
+        store_dir: cli.store_dir,
+        clip_timeout: cli.clip_timeout,
+    })?;
+    // SAFETY: `umask()` always succeeds and only swaps the mask.
+    unsafe {
+        libc::umask(config.umask as libc::mode_t);
+    }
+    if let Subcommand::Init(_) = cli.subcommand {
+        PasswordStoreInterface::create_root(config.store_dir.as_str())?;
+    }
+    let command_runner = CommandRunner::new(config)?;
+    match cli.subcommand {
+        Subcommand::Completion(_) | Subcommand::Man(_) => unreachable!(),
+        Subcommand::Cp(args) => Ok(command_runner.cp(
+            args.source.to_str().unwrap(),
+            args.destination.to_str().unwrap(),
+            args.force,
+        )?),
         Subcommand::Edit(args) => Ok(command_runner.edit(args.target.to_str().unwrap())?),
-        Subcommand::Find(args) => Ok(command_runner.find(args.keyword.to_str().unwrap())?),
-        Subcommand::Show(args) => {
-            let dest = match args.show_to {
-                Some(show_to) => {
-                    if show_to.clip {
-                        ShowDestination::Clip
-                    } else if show_to.qrcode {
-                        ShowDestination::QrCode
-                    } else {
-                        panic!("BUG: unhandled `ShowTo` arm")
+        Subcommand::Find(args) => {
+            let patterns = args
+                .keywords
+                .iter()
+                .map(|keyword| match args.glob {
+                    true => search::compile_glob(keyword, args.ignore_case),
+                    false => {
+                        search::compile_pattern(keyword, args.ignore_case, args.extended_regexp)
                     }
-                }
-                None => ShowDestination::Stdout,
+                })
+                .collect::<Result<Vec<_>, RadomskoError>>()?;
+            let search = search::SearchTerms::new(patterns, args.all);
+            Ok(command_runner.find(&search, args.format)?)
+        }
+        Subcommand::Generate(args) => {
+            let generator = PasswordGenerator::new(
+                !args.no_symbols,
+                args.no_ambiguous,
+                args.require_each_class,
+            );
+            Ok(command_runner.generate(
+                args.target.to_str().unwrap(),
+                &generator,
+                args.length,
+                args.in_place,
+                args.force,
+                whole_show_destination(&args.show_to),
+            )?)
+        }
+        Subcommand::Git(args) => Ok(command_runner.git(&args.args)?),
+        Subcommand::Grep(args) => {
+            let pattern =
+                search::compile_pattern(&args.pattern, args.ignore_case, args.extended_regexp)?;
+            let subdirectory = match args.subdirectory {
+                Some(path) => path.to_str().unwrap().to_owned(),
+                None => String::new(),
             };
+            Ok(command_runner.grep(&pattern, subdirectory.as_str())?)
+        }
+        Subcommand::Init(args) => {
+            let subdirectory = match args.path {
+                Some(path) => path.to_str().unwrap().to_owned(),
+                None => String::new(),
+            };
+            let format = match args.age {
+                true => EntryFormat::Age,
+                false => EntryFormat::Gpg,
+            };
+            Ok(command_runner.init(subdirectory.as_str(), format, &args.gpg_ids)?)
+        }
+        Subcommand::Insert(args) => {
+            Ok(command_runner.insert(args.target.to_str().unwrap(), args.multiline, args.force)?)
+        }
+        Subcommand::Mv(args) => Ok(command_runner.mv(
+            args.source.to_str().unwrap(),
+            args.destination.to_str().unwrap(),
+            args.force,
+        )?),
+        Subcommand::Otp(args) => Ok(command_runner.otp(
+            args.target.to_str().unwrap(),
+            whole_show_destination(&args.show_to),
+        )?),
+        Subcommand::Pick(args) => {
+            let (dest, line) = show_destination(args.show_to.as_ref());
+            let selection = match line {
+                Some(line) => ShowSelection::Line(line),
+                None => ShowSelection::Default,
+            };
+            let query = args.query.unwrap_or_default();
+            Ok(command_runner.pick(query.as_str(), dest, selection)?)
+        }
+        Subcommand::Rm(args) => {
+            Ok(command_runner.rm(args.target.to_str().unwrap(), args.recursive, args.force)?)
+        }
+        Subcommand::Show(args) => {
+            let (dest, line) = show_destination(args.show_to.as_ref());
+            let selection = show_selection(&args, line).unwrap_or_else(|e| e.exit());
             let target = match args.target {
                 Some(targ) => targ.to_str().unwrap().to_owned(),
                 None => String::new(),
             };
-            Ok(command_runner.show(target.as_str(), dest)?)
+            Ok(command_runner.show(target.as_str(), dest, selection, args.format)?)
         }
     }
 }
 
 fn main() {
+    clap_complete::CompleteEnv::with_factory(Cli::command)
+        .var(completion::COMPLETE_ENV)
+        .complete();
     match main_impl() {
         Ok(_) => (),
         Err(e) => {
-            eprintln!("Error: {:#?}", e);
-            std::process::exit(1);
+            eprintln!("Error: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns a `CommandRunner` acting on a scratch password store that
+    // holds empty passwords at each of the relative `passwords`. The
+    // store is deleted when the returned `TempDir` is dropped.
+    fn scratch_command_runner(passwords: &[&str]) -> (tempfile::TempDir, CommandRunner) {
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("command-runner-fixture-")
+            .tempdir()
+            .unwrap();
+        let root = tmp_dir.path().join("store");
+        for password in passwords {
+            let path = root.join(password);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        std::fs::create_dir_all(&root).unwrap();
+        // Keeps the real environment and configuration file out of it.
+        let xdg_config_home = tmp_dir.path().to_str().unwrap().to_owned();
+        let env = move |name: &str| match name {
+            "XDG_CONFIG_HOME" => Some(xdg_config_home.clone()),
+            _ => None,
+        };
+        let config = Config::load_impl(
+            config::CommandLineOverrides {
+                config: None,
+                store_dir: Some(root.to_str().unwrap().to_owned()),
+                clip_timeout: None,
+            },
+            &env,
+        )
+        .unwrap();
+        (tmp_dir, CommandRunner::new(config).unwrap())
+    }
+
+    #[test]
+    fn insert_refuses_existing_password() {
+        let (_tmp_dir, command_runner) = scratch_command_runner(&["web/example.com.gpg"]);
+        assert_eq!(
+            command_runner
+                .insert("web/example.com", false, false)
+                .unwrap_err(),
+            RadomskoError::AlreadyExists("web/example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn insert_rejects_empty_password() {
+        let (_tmp_dir, command_runner) = scratch_command_runner(&[]);
+        for cleartext in ["", "\n", " \t\n\n"] {
+            assert!(matches!(
+                command_runner.insert_cleartext("example.com", cleartext),
+                Err(RadomskoError::BadInput { .. })
+            ));
+        }
+        assert!(!command_runner
+            .password_store
+            .root()
+            .join("example.com.gpg")
+            .exists());
+    }
+
+    #[test]
+    fn rm_fails_before_confirming_missing_password() {
+        let (_tmp_dir, command_runner) = scratch_command_runner(&["a.gpg"]);
+        assert_eq!(
+            command_runner.rm("b", false, false).unwrap_err(),
+            RadomskoError::NotFound("b".to_string())
+        );
+    }
+
+    #[test]
+    fn failed_transfer_leaves_nothing_behind() {
+        // The empty `.gpg-id` is copied before the password under it
+        // fails for want of recipients.
+        let (_tmp_dir, command_runner) = scratch_command_runner(&["a/.gpg-id", "a/b/c.gpg"]);
+        let err = command_runner.cp("a", "d/e", false).unwrap_err();
+        assert!(matches!(err, RadomskoError::NoRecipients { .. }));
+        let root = command_runner.password_store.root();
+        assert!(!root.join("d").exists());
+        assert!(root.join("a/b/c.gpg").is_file());
+    }
+
+    #[test]
+    fn init_skips_passwords_with_unchanged_recipients() {
+        let (_tmp_dir, command_runner) = scratch_command_runner(&["a/b.gpg"]);
+        let root = command_runner.password_store.root().to_path_buf();
+        std::fs::write(root.join("a/.gpg-id"), "alice@example.com\n").unwrap();
+        // `a/b.gpg` is not a real password, so decrypting it would fail.
+        command_runner
+            .init("a", EntryFormat::Gpg, &["alice@example.com".to_string()])
+            .unwrap();
+        assert_eq!(std::fs::read(root.join("a/b.gpg")).unwrap(), b"");
+    }
+
+    #[test]
+    fn failed_init_keeps_recipients() {
+        let (_tmp_dir, command_runner) = scratch_command_runner(&["a/b.age"]);
+        let root = command_runner.password_store.root().to_path_buf();
+        std::fs::write(root.join("a/.gpg-id"), "alice@example.com\n").unwrap();
+        // No age identities are configured to decrypt `a/b.age` with.
+        let err = command_runner
+            .init("a", EntryFormat::Age, &["age1alice".to_string()])
+            .unwrap_err();
+        assert!(matches!(err.root_cause(), RadomskoError::BadInput { .. }));
+        assert_eq!(
+            std::fs::read_to_string(root.join("a/.gpg-id")).unwrap(),
+            "alice@example.com\n"
+        );
+        assert!(!root.join("a/.age-recipients").exists());
+    }
+
+    // Parses the arguments to `show` in `args`, which follow the
+    // program name.
+    fn show_args(args: &[&str]) -> ShowArgs {
+        let cli = Cli::try_parse_from(std::iter::once("radomsko").chain(args.iter().copied()));
+        match cli.unwrap().subcommand {
+            Subcommand::Show(args) => args,
+            _ => panic!("not a show command"),
+        }
+    }
+
+    #[test]
+    fn show_selection_rejects_line_with_field() {
+        for args in [
+            ["show", "-c=2", "--field", "user"].as_slice(),
+            ["show", "-q=1", "--fields"].as_slice(),
+        ] {
+            let args = show_args(args);
+            let (_, line) = show_destination(args.show_to.as_ref());
+            let err = show_selection(&args, line).unwrap_err();
+            assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+
+        let args = show_args(&["show", "-c", "--field", "user"]);
+        let (_, line) = show_destination(args.show_to.as_ref());
+        assert_eq!(
+            show_selection(&args, line).unwrap(),
+            ShowSelection::Field("user".to_string())
+        );
+    }
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        Cli::try_parse_from(with_attached_line_numbers(args))
+    }
+
+    #[test]
+    fn attached_line_numbers_follow_global_options() {
+        let cli = parse(&[
+            "radomsko",
+            "--store-dir",
+            "/tmp/store",
+            "--clip-timeout=5",
+            "show",
+            "-c1",
+            "email",
+        ])
+        .unwrap();
+        assert_eq!(cli.store_dir.as_deref(), Some("/tmp/store"));
+        match cli.subcommand {
+            Subcommand::Show(args) => {
+                assert_eq!(
+                    show_destination(args.show_to.as_ref()),
+                    (ShowDestination::Clip, Some(1))
+                );
+                assert_eq!(args.target, Some(PathBuf::from("email")));
+            }
+            _ => panic!("not a show command"),
+        }
+
+        let cli = parse(&["radomsko", "--config", "/tmp/c.toml", "pick", "-q2"]).unwrap();
+        match cli.subcommand {
+            Subcommand::Pick(args) => assert_eq!(
+                show_destination(args.show_to.as_ref()),
+                (ShowDestination::QrCode, Some(2))
+            ),
+            _ => panic!("not a pick command"),
+        }
+
+        let cli = parse(&["radomsko", "show", "-c2", "foo"]).unwrap();
+        match cli.subcommand {
+            Subcommand::Show(args) => {
+                assert_eq!(
+                    show_destination(args.show_to.as_ref()),
+                    (ShowDestination::Clip, Some(2))
+                );
+                assert_eq!(args.target, Some(PathBuf::from("foo")));
+            }
+            _ => panic!("not a show command"),
+        }
+    }
+
+    #[test]
+    fn line_numbers_rejected_without_lines_to_pick() {
+        for args in [
+            ["radomsko", "generate", "-c2", "foo"].as_slice(),
+            ["radomsko", "generate", "-q=1", "foo"].as_slice(),
+            ["radomsko", "otp", "-c2", "foo"].as_slice(),
+        ] {
+            assert!(parse(args).is_err());
+        }
+
+        let cli = parse(&["radomsko", "otp", "-c", "foo"]).unwrap();
+        match cli.subcommand {
+            Subcommand::Otp(args) => {
+                assert_eq!(whole_show_destination(&args.show_to), ShowDestination::Clip)
+            }
+            _ => panic!("not an otp command"),
+        }
+    }
+
+    #[test]
+    fn attached_line_numbers_only_for_show_to() {
+        // `git` passes its arguments through untouched.
+        let cli = parse(&["radomsko", "git", "log", "-c1"]).unwrap();
+        match cli.subcommand {
+            Subcommand::Git(args) => assert_eq!(args.args, vec!["log", "-c1"]),
+            _ => panic!("not a git command"),
+        }
+        let cli = parse(&["radomsko", "show", "--", "-c1"]).unwrap();
+        match cli.subcommand {
+            Subcommand::Show(args) => assert_eq!(args.target, Some(PathBuf::from("-c1"))),
+            _ => panic!("not a show command"),
         }
     }
 }
@@ -0,0 +1,345 @@
+// Computes one-time passwords (RFC 4226 HOTP and RFC 6238 TOTP) from
+// `otpauth://` URIs stored in passwords.
+
+use std::ops::Range;
+
+use hmac::{Hmac, Mac};
+
+use crate::enums::RadomskoError;
+
+const OTPAUTH_SCHEME: &str = "otpauth://";
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OtpKind {
+    Totp { period: u64 },
+    Hotp { counter: u64 },
+}
+
+// The parameters of an `otpauth://` URI.
+#[derive(Debug, PartialEq)]
+pub struct OtpAuth {
+    pub kind: OtpKind,
+    secret: Vec<u8>,
+    algorithm: OtpAlgorithm,
+    digits: u32,
+}
+
+fn bad_uri(reason: &str) -> RadomskoError {
+    RadomskoError::BadInput(format!("bad otpauth URI: {}", reason))
+}
+
+// Decodes an RFC 4648 base32 secret, tolerating lowercase, spaces and
+// padding as commonly found in the wild.
+fn decode_secret(secret: &str) -> Result<Vec<u8>, RadomskoError> {
+    let normalized: String = secret
+        .chars()
+        .filter(|c| *c != '=' && *c != ' ')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if normalized.is_empty() {
+        return Err(bad_uri("empty secret"));
+    }
+    data_encoding::BASE32_NOPAD
+        .decode(normalized.as_bytes())
+        .map_err(|_| bad_uri("secret is not base32"))
+}
+
+fn hmac_digest(algorithm: OtpAlgorithm, key: &[u8], message: &[u8]) -> Vec<u8> {
+    match algorithm {
+        OtpAlgorithm::Sha1 => {
+            let mut mac = Hmac::<sha1::Sha1>::new_from_slice(key).unwrap();
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        OtpAlgorithm::Sha256 => {
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).unwrap();
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        OtpAlgorithm::Sha512 => {
+            let mut mac = Hmac::<sha2::Sha512>::new_from_slice(key).unwrap();
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+impl OtpAuth {
+    pub fn parse(uri: &str) -> Result<OtpAuth, RadomskoError> {
+        let rest = match uri.trim().strip_prefix(OTPAUTH_SCHEME) {
+            Some(rest) => rest,
+            None => return Err(bad_uri("missing otpauth:// scheme")),
+        };
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let kind = path.split('/').next().unwrap_or("").to_ascii_lowercase();
+
+        let mut secret: Option<Vec<u8>> = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+        let mut digits = DEFAULT_DIGITS;
+        let mut period = DEFAULT_PERIOD;
+        let mut counter: Option<u64> = None;
+        for parameter in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(decode_secret(value)?),
+                "algorithm" => {
+                    algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => OtpAlgorithm::Sha1,
+                        "SHA256" => OtpAlgorithm::Sha256,
+                        "SHA512" => OtpAlgorithm::Sha512,
+                        _ => return Err(bad_uri("unsupported algorithm")),
+                    }
+                }
+                "digits" => {
+                    digits = value.parse().map_err(|_| bad_uri("bad digits"))?;
+                    if !(1..=10).contains(&digits) {
+                        return Err(bad_uri("digits must be between 1 and 10"));
+                    }
+                }
+                "period" => {
+                    period = value.parse().map_err(|_| bad_uri("bad period"))?;
+                    if period == 0 {
+                        return Err(bad_uri("period must be positive"));
+                    }
+                }
+                // `with_incremented_counter()` couldn't tell which of
+                // several to advance.
+                "counter" if counter.is_some() => {
+                    return Err(bad_uri("bad counter: given more than once"))
+                }
+                "counter" => counter = Some(value.parse().map_err(|_| bad_uri("bad counter"))?),
+                _ => (),
+            }
+        }
+
+        let kind = match kind.as_str() {
+            "totp" => OtpKind::Totp { period },
+            "hotp" => match counter {
+                Some(counter) => OtpKind::Hotp { counter },
+                None => return Err(bad_uri("hotp requires a counter")),
+            },
+            _ => return Err(bad_uri("type must be totp or hotp")),
+        };
+        match secret {
+            Some(secret) => Ok(OtpAuth {
+                kind,
+                secret,
+                algorithm,
+                digits,
+            }),
+            None => Err(bad_uri("missing secret")),
+        }
+    }
+
+    // Finds and parses the first `otpauth://` URI in `cleartext`.
+    //
+    // Returns the byte range of `cleartext` that the URI spans (up to
+    // the end of its line, less trailing whitespace) along with it.
+    pub fn find_in(cleartext: &str) -> Result<(Range<usize>, OtpAuth), RadomskoError> {
+        let mut offset = 0;
+        for line in cleartext.split_inclusive('\n') {
+            if let Some(start) = line.find(OTPAUTH_SCHEME) {
+                let uri = line[start..].trim_end();
+                let span = offset + start..offset + start + uri.len();
+                return Ok((span, OtpAuth::parse(uri)?));
+            }
+            offset += line.len();
+        }
+        Err(RadomskoError::BadInput(
+            "no otpauth:// URI found".to_string(),
+        ))
+    }
+
+    // Computes the RFC 4226 code for `counter`.
+    fn code_for_counter(&self, counter: u64) -> String {
+        let digest = hmac_digest(self.algorithm, &self.secret, &counter.to_be_bytes());
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let truncated =
+            u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+        let code = truncated as u64 % 10u64.pow(self.digits);
+        format!("{:0width$}", code, width = self.digits as usize)
+    }
+
+    // Computes the current code, treating `unix_time` as the present
+    // for TOTP.
+    pub fn code(&self, unix_time: u64) -> String {
+        match self.kind {
+            OtpKind::Totp { period } => self.code_for_counter(unix_time / period),
+            OtpKind::Hotp { counter } => self.code_for_counter(counter),
+        }
+    }
+}
+
+// Returns `uri` with its `counter` parameter advanced by one, leaving
+// everything else untouched.
+pub fn with_incremented_counter(uri: &str) -> Result<String, RadomskoError> {
+    let (base, query) = match uri.split_once('?') {
+        Some(split) => split,
+        None => return Err(bad_uri("hotp requires a counter")),
+    };
+    let mut found = false;
+    let mut parameters: Vec<String> = Vec::new();
+    for parameter in query.split('&') {
+        match parameter.split_once('=') {
+            Some((key, _)) if key.eq_ignore_ascii_case("counter") && found => {
+                return Err(bad_uri("bad counter: given more than once"));
+            }
+            Some((key, value)) if key.eq_ignore_ascii_case("counter") => {
+                let counter: u64 = value.parse().map_err(|_| bad_uri("bad counter"))?;
+                let counter = counter
+                    .checked_add(1)
+                    .ok_or_else(|| bad_uri("counter cannot advance any further"))?;
+                parameters.push(format!("{}={}", key, counter));
+                found = true;
+            }
+            _ => parameters.push(parameter.to_owned()),
+        }
+    }
+    if !found {
+        return Err(bad_uri("hotp requires a counter"));
+    }
+    Ok(format!("{}?{}", base, parameters.join("&")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The RFC 6238 test secrets, base32-encoded.
+    const RFC_SHA1_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const RFC_SHA256_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+    const RFC_SHA512_SECRET: &str =
+        "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA";
+
+    fn totp(secret: &str, algorithm: &str) -> OtpAuth {
+        OtpAuth::parse(
+            format!(
+                "otpauth://totp/Example:klaus?secret={}&algorithm={}&digits=8",
+                secret, algorithm
+            )
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rfc6238_sha1() {
+        let otp = totp(RFC_SHA1_SECRET, "SHA1");
+        assert_eq!(otp.code(59), "94287082");
+        assert_eq!(otp.code(1111111109), "07081804");
+        assert_eq!(otp.code(20000000000), "65353130");
+    }
+
+    #[test]
+    fn rfc6238_sha256() {
+        let otp = totp(RFC_SHA256_SECRET, "SHA256");
+        assert_eq!(otp.code(59), "46119246");
+        assert_eq!(otp.code(1111111109), "68084774");
+    }
+
+    #[test]
+    fn rfc6238_sha512() {
+        let otp = totp(RFC_SHA512_SECRET, "SHA512");
+        assert_eq!(otp.code(59), "90693936");
+        assert_eq!(otp.code(1111111109), "25091201");
+    }
+
+    #[test]
+    fn rfc4226_hotp() {
+        let expected = ["755224", "287082", "359152", "969429", "338314"];
+        for (counter, code) in expected.iter().enumerate() {
+            let otp = OtpAuth::parse(
+                format!(
+                    "otpauth://hotp/klaus?secret={}&counter={}",
+                    RFC_SHA1_SECRET, counter
+                )
+                .as_str(),
+            )
+            .unwrap();
+            assert_eq!(otp.code(0), *code);
+        }
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let otp = OtpAuth::parse("otpauth://totp/klaus?secret=jbswy3dpehpk3pxp").unwrap();
+        assert_eq!(otp.kind, OtpKind::Totp { period: 30 });
+        assert_eq!(otp.algorithm, OtpAlgorithm::Sha1);
+        assert_eq!(otp.digits, 6);
+        assert_eq!(otp.code(0).len(), 6);
+    }
+
+    #[test]
+    fn parse_custom_period() {
+        let otp = OtpAuth::parse(
+            format!("otpauth://totp/klaus?period=60&secret={}", RFC_SHA1_SECRET).as_str(),
+        )
+        .unwrap();
+        assert_eq!(otp.kind, OtpKind::Totp { period: 60 });
+        assert_eq!(otp.code(60), otp.code(119));
+        assert_ne!(otp.code(119), otp.code(120));
+    }
+
+    #[test]
+    fn parse_rejects_bad_uris() {
+        for uri in [
+            "https://example.com",
+            "otpauth://totp/klaus",
+            "otpauth://hotp/klaus?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://motp/klaus?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/klaus?secret=!!!",
+            "otpauth://totp/klaus?secret=JBSWY3DPEHPK3PXP&algorithm=MD5",
+            "otpauth://totp/klaus?secret=JBSWY3DPEHPK3PXP&period=0",
+            "otpauth://hotp/klaus?secret=JBSWY3DPEHPK3PXP&counter=1&Counter=5",
+        ] {
+            let err = OtpAuth::parse(uri).unwrap_err();
+            assert!(matches!(err, RadomskoError::BadInput { .. }), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn find_in_cleartext() {
+        let cleartext = "hunter2\r\nusername: klaus\r\n\
+            otp: otpauth://hotp/klaus?secret=JBSWY3DPEHPK3PXP&counter=3 \r\nnotes";
+        let (span, otp) = OtpAuth::find_in(cleartext).unwrap();
+        assert_eq!(
+            &cleartext[span],
+            "otpauth://hotp/klaus?secret=JBSWY3DPEHPK3PXP&counter=3"
+        );
+        assert_eq!(otp.kind, OtpKind::Hotp { counter: 3 });
+
+        let err = OtpAuth::find_in("hunter2\n").unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn increment_counter() {
+        assert_eq!(
+            with_incremented_counter(
+                "otpauth://hotp/klaus?secret=JBSWY3DPEHPK3PXP&counter=9&issuer=Example"
+            )
+            .unwrap(),
+            "otpauth://hotp/klaus?secret=JBSWY3DPEHPK3PXP&counter=10&issuer=Example"
+        );
+        assert!(with_incremented_counter("otpauth://hotp/klaus?secret=A").is_err());
+
+        let err = with_incremented_counter(
+            format!("otpauth://hotp/klaus?secret=A&counter={}", u64::MAX).as_str(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+
+        let err = with_incremented_counter("otpauth://hotp/klaus?secret=A&counter=1&counter=5")
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::enums::RadomskoError;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+// Characters that are easily confused for one another when read back
+// by a human.
+const AMBIGUOUS: &str = "0O1Il|`'\"";
+
+// Generates random passwords from a cryptographically secure source.
+#[derive(Debug)]
+pub struct PasswordGenerator {
+    classes: Vec<Vec<char>>,
+    require_each_class: bool,
+}
+
+fn character_class(characters: &str, exclude_ambiguous: bool) -> Vec<char> {
+    characters
+        .chars()
+        .filter(|c| !exclude_ambiguous || !AMBIGUOUS.contains(*c))
+        .collect()
+}
+
+// Returns a uniformly distributed index in `0..bound`.
+//
+// Rejects samples from the biased tail of the `u32` range rather than
+// reducing them modulo `bound`.
+fn random_index(bound: usize) -> Result<usize, RadomskoError> {
+    assert!(bound > 0 && bound <= u32::MAX as usize);
+    let bound = bound as u32;
+    let zone = u32::MAX - (u32::MAX % bound);
+    loop {
+        let mut buf = [0u8; 4];
+        getrandom::fill(&mut buf)
+            .map_err(|e| RadomskoError::IoError(format!("getrandom: {}", e)))?;
+        let sample = u32::from_ne_bytes(buf);
+        if sample < zone {
+            return Ok((sample % bound) as usize);
+        }
+    }
+}
+
+impl PasswordGenerator {
+    // Arguments:
+    // *    `symbols` - whether punctuation is included at all.
+    // *    `exclude_ambiguous` - drops look-alike characters from every
+    //          class.
+    // *    `require_each_class` - demands that every generated password
+    //          contain at least one character from each class.
+    pub fn new(
+        symbols: bool,
+        exclude_ambiguous: bool,
+        require_each_class: bool,
+    ) -> PasswordGenerator {
+        let mut classes = vec![
+            character_class(LOWERCASE, exclude_ambiguous),
+            character_class(UPPERCASE, exclude_ambiguous),
+            character_class(DIGITS, exclude_ambiguous),
+        ];
+        if symbols {
+            classes.push(character_class(SYMBOLS, exclude_ambiguous));
+        }
+        PasswordGenerator {
+            classes,
+            require_each_class,
+        }
+    }
+
+    fn satisfies_required_classes(&self, password: &[char]) -> bool {
+        self.classes
+            .iter()
+            .all(|class| password.iter().any(|c| class.contains(c)))
+    }
+
+    pub fn generate(&self, length: usize) -> Result<String, RadomskoError> {
+        if length == 0 {
+            return Err(RadomskoError::BadInput(
+                "password length must be positive".to_string(),
+            ));
+        }
+        if self.require_each_class && length < self.classes.len() {
+            return Err(RadomskoError::BadInput(format!(
+                "password length must be at least {} to use every character class",
+                self.classes.len()
+            )));
+        }
+
+        let alphabet: Vec<char> = self.classes.concat();
+        // Redrawing the whole password (instead of patching in missing
+        // classes) keeps every acceptable password equally likely.
+        loop {
+            let mut password: Vec<char> = Vec::with_capacity(length);
+            for _ in 0..length {
+                password.push(alphabet[random_index(alphabet.len())?]);
+            }
+            if !self.require_each_class || self.satisfies_required_classes(&password) {
+                return Ok(password.into_iter().collect());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_honors_length() {
+        let generator = PasswordGenerator::new(true, false, false);
+        assert_eq!(generator.generate(37).unwrap().chars().count(), 37);
+    }
+
+    #[test]
+    fn generate_rejects_zero_length() {
+        let generator = PasswordGenerator::new(true, false, false);
+        let err = generator.generate(0).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn generate_without_symbols() {
+        let generator = PasswordGenerator::new(false, false, false);
+        let password = generator.generate(200).unwrap();
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn generate_without_ambiguous_characters() {
+        let generator = PasswordGenerator::new(true, true, false);
+        let password = generator.generate(200).unwrap();
+        assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+    }
+
+    #[test]
+    fn generate_requiring_each_class() {
+        let generator = PasswordGenerator::new(true, false, true);
+        for _ in 0..50 {
+            let password = generator.generate(4).unwrap();
+            assert!(password.chars().any(|c| c.is_ascii_lowercase()));
+            assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+            assert!(password.chars().any(|c| c.is_ascii_digit()));
+            assert!(password.chars().any(|c| c.is_ascii_punctuation()));
+        }
+    }
+
+    #[test]
+    fn generate_requiring_each_class_rejects_short_length() {
+        let generator = PasswordGenerator::new(false, false, true);
+        let err = generator.generate(2).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+}
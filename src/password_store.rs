@@ -1,10 +1,51 @@
-use std::path::{Path, PathBuf};
+use std::ops::Range;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Component, Path, PathBuf};
 
 use colorful::Colorful;
 
-use crate::enums::RadomskoError;
+use crate::enums::{io_error_at, ListingFormat, RadomskoError};
+use crate::search::SearchTerms;
 
-const GPG_EXTENSION: &'static str = "gpg";
+const GPG_EXTENSION: &str = "gpg";
+const GPG_ID_FILE: &str = ".gpg-id";
+const AGE_EXTENSION: &str = "age";
+const AGE_RECIPIENTS_FILE: &str = ".age-recipients";
+const PASSWORD_STORE_ROOT_PERMISSIONS: u32 = 0o700;
+
+// How a password is encrypted. Its extension says which format it
+// is in, and the recipients file nearest to it says which format it
+// should be written in; the two differ while a store is migrated.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum EntryFormat {
+    Gpg,
+    Age,
+}
+
+impl EntryFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EntryFormat::Gpg => GPG_EXTENSION,
+            EntryFormat::Age => AGE_EXTENSION,
+        }
+    }
+
+    // Returns the name of the per-directory file listing recipients.
+    pub fn recipients_file(&self) -> &'static str {
+        match self {
+            EntryFormat::Gpg => GPG_ID_FILE,
+            EntryFormat::Age => AGE_RECIPIENTS_FILE,
+        }
+    }
+
+    // Returns the format of the password at `path`.
+    pub fn of(path: &Path) -> EntryFormat {
+        match path.extension() {
+            Some(extension) if extension == AGE_EXTENSION => EntryFormat::Age,
+            _ => EntryFormat::Gpg,
+        }
+    }
+}
 
 // Interacts with the configured root of the password store.
 // `root` must be readable at time of instantiation.
@@ -12,6 +53,8 @@ const GPG_EXTENSION: &'static str = "gpg";
 pub struct PasswordStoreInterface {
     root: PathBuf,
     colorize_display: bool,
+    // Used in place of every `.gpg-id` file if set.
+    recipients_override: Option<Vec<String>>,
 }
 
 fn default_password_store_root() -> PathBuf {
@@ -20,14 +63,56 @@ fn default_password_store_root() -> PathBuf {
     path
 }
 
-// Helper filter for `PasswordStoreInterface::draw_tree()`.
-fn is_gpg_file(path: &PathBuf) -> bool {
-    path.is_file() && path.to_str().unwrap().ends_with(GPG_EXTENSION)
+fn password_store_root(configured_root: &str) -> PathBuf {
+    match configured_root.is_empty() {
+        true => default_password_store_root(),
+        false => PathBuf::from(configured_root),
+    }
 }
 
 // Helper filter for `PasswordStoreInterface::draw_tree()`.
-fn dirent_matches_search_term(path: &PathBuf, search_term: &str) -> bool {
-    path.to_str().unwrap().contains(search_term)
+pub fn is_password_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension == GPG_EXTENSION || extension == AGE_EXTENSION)
+}
+
+fn is_recipients_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .file_name()
+            .is_some_and(|name| name == GPG_ID_FILE || name == AGE_RECIPIENTS_FILE)
+}
+
+// Appends the extension of `format` to `path`.
+fn with_extension(mut path: PathBuf, format: EntryFormat) -> PathBuf {
+    // If the symbolic password name has a dot in its name, `set_extension()`
+    // will think that it has an extension (and wrongly eat it).
+    if path.extension().is_some() {
+        path.set_file_name(format!(
+            "{}.{}",
+            path.file_name().unwrap().to_str().unwrap(),
+            format.extension()
+        ));
+    } else {
+        path.set_extension(format.extension());
+    }
+    path
+}
+
+// Parses the contents of a recipients file into a sorted list of
+// recipients. Blank lines and `#` comments are ignored.
+fn parse_recipients(contents: &str) -> Vec<String> {
+    let mut recipients: Vec<String> = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_owned())
+        .collect();
+    recipients.sort();
+    recipients.dedup();
+    recipients
 }
 
 // Helper filter-map for `PasswordStoreInterface::draw_tree()`.
@@ -38,24 +123,106 @@ fn ok_dirent_as_pathbuf(entry: Result<walkdir::DirEntry, walkdir::Error>) -> Opt
     }
 }
 
-// Helper formatter for `PasswordStoreInterface::draw_tree_branch()`.
-fn tree_branch_with_indent(component: &std::ffi::OsStr, indent: usize, colorize: bool) -> String {
-    if colorize {
-        let pink = colorful::RGB::new(195, 91, 156);
-        return format!(
-            "{}*   {}",
-            "    ".repeat(indent),
-            component.to_str().unwrap()
-        )
-        .color(pink)
+// One entry or directory of a listing, as serialized for
+// `ListingFormat::Json`.
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ListingNode {
+    Directory {
+        name: String,
+        entries: Vec<ListingNode>,
+    },
+    Entry {
+        name: String,
+        path: String,
+    },
+}
+
+// Nests the symbolic `names` of passwords under their directories.
+// Expects the passwords in each directory to be listed together, as
+// `walk_tree()` does.
+fn nest_names(names: &[String]) -> Vec<ListingNode> {
+    let mut result: Vec<ListingNode> = Vec::new();
+    for name in names {
+        let components: Vec<&str> = name.split('/').collect();
+        let (leaf, directories) = components.split_last().unwrap();
+        let mut level = &mut result;
+        for directory in directories {
+            let is_open = matches!(
+                level.last(),
+                Some(ListingNode::Directory { name, .. }) if name == directory
+            );
+            if !is_open {
+                level.push(ListingNode::Directory {
+                    name: directory.to_string(),
+                    entries: Vec::new(),
+                });
+            }
+            level = match level.last_mut() {
+                Some(ListingNode::Directory { entries, .. }) => entries,
+                _ => unreachable!(),
+            };
+        }
+        level.push(ListingNode::Entry {
+            name: leaf.to_string(),
+            path: name.clone(),
+        });
+    }
+    result
+}
+
+fn highlight_color() -> colorful::RGB {
+    colorful::RGB::new(195, 91, 156)
+}
+
+// Renders `text` in radomsko's highlight style.
+pub fn highlight(text: &str) -> String {
+    text.color(highlight_color()).bold().to_string()
+}
+
+// Renders `text` in radomsko's highlight style, underlined to stand
+// out from highlighted text around it.
+fn highlight_underlined(text: &str) -> String {
+    text.color(highlight_color())
         .bold()
-        .to_string();
+        .underlined()
+        .to_string()
+}
+
+// Helper formatter for `PasswordStoreInterface::draw_tree_branch()`.
+//
+// Highlights the whole line if `colorize` is set, as well as the
+// `matched` byte ranges of `component`, which are underlined in
+// highlighted lines.
+fn tree_branch_with_indent(
+    component: &str,
+    indent: usize,
+    colorize: bool,
+    matched: &[Range<usize>],
+) -> String {
+    let line = format!("{}*   {}", "    ".repeat(indent), component);
+    let unmatched_style = |text: &str| match colorize && !text.is_empty() {
+        true => highlight(text),
+        false => text.to_owned(),
+    };
+    let matched_style = |text: &str| match colorize {
+        true => highlight_underlined(text),
+        false => highlight(text),
+    };
+    if matched.is_empty() {
+        return unmatched_style(line.as_str());
+    }
+
+    let offset = line.len() - component.len();
+    let mut result = String::new();
+    let mut last = 0;
+    for range in matched {
+        result.push_str(unmatched_style(&line[last..offset + range.start]).as_str());
+        result.push_str(matched_style(&component[range.clone()]).as_str());
+        last = offset + range.end;
     }
-    format!(
-        "{}*   {}",
-        "    ".repeat(indent),
-        component.to_str().unwrap()
-    )
+    result.push_str(unmatched_style(&line[last..]).as_str());
+    result
 }
 
 impl PasswordStoreInterface {
@@ -63,25 +230,54 @@ impl PasswordStoreInterface {
         configured_root: &str,
         colorize_display: bool,
     ) -> Result<PasswordStoreInterface, RadomskoError> {
-        let root = match configured_root.is_empty() {
-            true => default_password_store_root(),
-            false => PathBuf::from(configured_root),
-        };
+        let root = password_store_root(configured_root);
 
-        if !std::fs::metadata(root.as_path())?.is_dir() {
-            return Err(RadomskoError::NotFound);
+        let metadata =
+            std::fs::metadata(root.as_path()).map_err(io_error_at("open password store", &root))?;
+        if !metadata.is_dir() {
+            return Err(RadomskoError::IoError(format!(
+                "password store {} is not a directory",
+                root.display()
+            )));
         }
+        // Paths in the store are canonicalized before they are checked
+        // against `root`, so it must be canonical too.
+        let root = root.canonicalize().map_err(io_error_at("resolve", &root))?;
 
         Ok(PasswordStoreInterface {
-            root: root,
-            colorize_display: colorize_display,
+            root,
+            colorize_display,
+            recipients_override: None,
         })
     }
 
+    // Makes every password use `recipients` regardless of the `.gpg-id`
+    // files in the store, like `PASSWORD_STORE_KEY` does for `pass`.
+    pub fn with_recipients_override(mut self, recipients: Vec<String>) -> PasswordStoreInterface {
+        self.recipients_override = Some(recipients);
+        self
+    }
+
+    // Creates the root of the password store (only accessible to the
+    // calling user) if it does not yet exist.
+    pub fn create_root(configured_root: &str) -> Result<(), RadomskoError> {
+        let root = password_store_root(configured_root);
+        if !root.is_dir() {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(PASSWORD_STORE_ROOT_PERMISSIONS)
+                .create(&root)
+                .map_err(io_error_at("create", &root))?;
+        }
+        Ok(())
+    }
+
     // Borrows a named `password` and returns the underlying path in the
     // password store.
     //
     // *    Does not require that the resulting path to `password` exist.
+    //      An existing password keeps its extension; otherwise the one
+    //      of `format_for()` is used.
     // *    _Does_ require that the containing dir to the resulting path
     //      exist.
     pub fn path_for(&self, password: &str) -> Result<PathBuf, RadomskoError> {
@@ -90,40 +286,427 @@ impl PasswordStoreInterface {
 
     // Borrows a relative `path` and returns the underlying path in the
     // password store.
-    fn path_for_impl(&self, path: &str, add_gpg_extension: bool) -> Result<PathBuf, RadomskoError> {
+    fn path_for_impl(&self, path: &str, add_extension: bool) -> Result<PathBuf, RadomskoError> {
         let mut full_path = self.root.clone();
         full_path.push(path);
 
-        let containing_dir = match full_path.parent() {
-            Some(parent) => parent,
-            None => return Err(RadomskoError::IoError(format!("bad path: {}", path))),
+        let (containing_dir, file_name) = match (full_path.parent(), full_path.file_name()) {
+            (Some(parent), Some(file_name)) => (parent, file_name),
+            _ => return Err(self.not_an_entry(path)),
         };
 
-        let mut canonical = containing_dir.canonicalize()?;
-        if !canonical.starts_with(&self.root) {
-            return Err(RadomskoError::IoError(format!("bad path: {}", path)));
-        }
-        canonical.push(full_path.file_name().unwrap());
-
-        if add_gpg_extension {
-            // If the symbolic password name has a dot in its name, `set_extension()`
-            // will think that it has an extension (and wrongly eat it).
-            if canonical.extension().is_some() {
-                canonical.set_file_name(format!(
-                    "{}.{}",
-                    canonical.file_name().unwrap().to_str().unwrap(),
-                    GPG_EXTENSION
-                ));
+        let mut canonical = containing_dir.canonicalize().map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                RadomskoError::NotFound(path.to_owned())
             } else {
-                canonical.set_extension(GPG_EXTENSION);
+                io_error_at("resolve", containing_dir)(e)
             }
+        })?;
+        canonical.push(file_name);
+        if !canonical.starts_with(&self.root) {
+            return Err(self.escapes_store(path, &canonical));
+        }
+
+        if add_extension {
+            canonical = self.with_password_extension(canonical);
         }
 
         Ok(canonical)
     }
 
+    // Aids `path_for_impl()` by appending the extension of an existing
+    // password at `path`, or else that of the format it would be
+    // written in.
+    fn with_password_extension(&self, path: PathBuf) -> PathBuf {
+        for format in [EntryFormat::Gpg, EntryFormat::Age] {
+            let existing = with_extension(path.clone(), format);
+            if existing.is_file() {
+                return existing;
+            }
+        }
+        let format = self.format_for(&path);
+        with_extension(path, format)
+    }
+
+    // Returns the error for a `name` that doesn't lead to anything
+    // that could be an entry, such as `root` itself.
+    fn not_an_entry(&self, name: &str) -> RadomskoError {
+        RadomskoError::BadInput(format!(
+            "``{}'' does not name an entry in the password store",
+            name
+        ))
+    }
+
+    // Returns the error for a `name` that leads outside of `root`, to
+    // `resolved`.
+    fn escapes_store(&self, name: &str, resolved: &Path) -> RadomskoError {
+        RadomskoError::BadInput(format!(
+            "``{}'' leads outside of the password store {} (to {})",
+            name,
+            self.root.display(),
+            resolved.display()
+        ))
+    }
+
+    // Borrows a relative `path` and resolves it against `root` without
+    // touching the filesystem, refusing to climb above `root`.
+    fn lexical_path_for(&self, path: &str) -> Result<PathBuf, RadomskoError> {
+        let mut result = self.root.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => result.push(name),
+                Component::CurDir => (),
+                Component::ParentDir if result != self.root => {
+                    result.pop();
+                }
+                _ => {
+                    // Finishes resolving `path` without the bound, to
+                    // tell where it leads.
+                    let mut escaped = self.root.clone();
+                    for component in Path::new(path).components() {
+                        match component {
+                            Component::ParentDir => {
+                                escaped.pop();
+                            }
+                            component => escaped.push(component),
+                        }
+                    }
+                    return Err(self.escapes_store(path, &escaped));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    // Like `lexical_path_for()`, but canonicalizes the directories
+    // containing `path` as far as they exist, so that symlinks cannot
+    // lead outside of `root`. Those that don't exist are left for
+    // `create_directories_for()`.
+    fn planned_path_for(&self, path: &str) -> Result<PathBuf, RadomskoError> {
+        let lexical = self.lexical_path_for(path)?;
+        let mut existing = match lexical.parent() {
+            Some(parent) if lexical != self.root => parent,
+            _ => return Err(self.not_an_entry(path)),
+        };
+        while !existing.exists() {
+            existing = existing.parent().ok_or_else(|| self.not_an_entry(path))?;
+        }
+        let canonical = existing
+            .canonicalize()
+            .map_err(io_error_at("resolve", existing))?
+            .join(lexical.strip_prefix(existing).unwrap());
+        if !canonical.starts_with(&self.root) {
+            return Err(self.escapes_store(path, &canonical));
+        }
+        Ok(canonical)
+    }
+
+    // Creates any missing directories containing `path`, which must lie
+    // lexically under `root`. Every directory is canonicalized as it is
+    // visited so that symlinks cannot lead outside of `root`.
+    //
+    // Returns the directories created, outermost first.
+    pub fn create_directories_for(&self, path: &Path) -> Result<Vec<PathBuf>, RadomskoError> {
+        let name = path.display().to_string();
+        let relative = match path.parent() {
+            Some(parent) => parent
+                .strip_prefix(&self.root)
+                .map_err(|_| self.escapes_store(&name, path))?,
+            None => return Err(self.not_an_entry(&name)),
+        };
+
+        let mut created: Vec<PathBuf> = Vec::new();
+        let mut current = self.root.clone();
+        for component in relative.components() {
+            current.push(component);
+            if !current.exists() {
+                std::fs::create_dir(&current).map_err(io_error_at("create", &current))?;
+                created.push(current.clone());
+            }
+            current = current
+                .canonicalize()
+                .map_err(io_error_at("resolve", &current))?;
+            if !current.starts_with(&self.root) {
+                return Err(self.escapes_store(&name, &current));
+            }
+        }
+        Ok(created)
+    }
+
+    // Returns the recipients file nearest to `path`, searching upward
+    // from its containing directory to `root`, along with the format it
+    // is for. Within one directory, `.age-recipients` comes first.
+    fn recipients_file_for(&self, path: &Path) -> Option<(EntryFormat, PathBuf)> {
+        let mut current = path.parent();
+        while let Some(directory) = current {
+            if !directory.starts_with(&self.root) {
+                break;
+            }
+            for format in [EntryFormat::Age, EntryFormat::Gpg] {
+                let recipients_file = directory.join(format.recipients_file());
+                if recipients_file.is_file() {
+                    return Some((format, recipients_file));
+                }
+            }
+            current = directory.parent();
+        }
+        None
+    }
+
+    // Returns the format that the password at `path` should be written
+    // in, which is gpg unless an `.age-recipients` file governs it.
+    pub fn format_for(&self, path: &Path) -> EntryFormat {
+        match self.recipients_file_for(path) {
+            Some((format, _)) => format,
+            None => EntryFormat::Gpg,
+        }
+    }
+
+    // Returns the recipients listed in the recipients file nearest to
+    // `path`. The override, if one is set, stands in for `.gpg-id`
+    // files.
+    //
+    // Returns `None` if no recipients file is found.
+    pub fn recipients_for(&self, path: &Path) -> Result<Option<Vec<String>>, RadomskoError> {
+        let recipients_file = match (self.recipients_file_for(path), &self.recipients_override) {
+            (Some((EntryFormat::Age, recipients_file)), _) => recipients_file,
+            (_, Some(recipients)) => return Ok(Some(recipients.clone())),
+            (Some((EntryFormat::Gpg, recipients_file)), None) => recipients_file,
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(parse_recipients(
+            std::fs::read_to_string(&recipients_file)
+                .map_err(io_error_at("read", &recipients_file))?
+                .as_str(),
+        )))
+    }
+
+    // Returns the recipients that a password in `format` is encrypted
+    // to when its recipients file lists `listed`. As in
+    // `recipients_for()`, the override stands in for `.gpg-id` files.
+    pub fn effective_recipients(&self, format: EntryFormat, listed: &[String]) -> Vec<String> {
+        match (format, &self.recipients_override) {
+            (EntryFormat::Gpg, Some(recipients)) => recipients.clone(),
+            _ => parse_recipients(listed.join("\n").as_str()),
+        }
+    }
+
+    // Returns the passwords under `subdirectory` that a recipients file
+    // there governs (or would govern), i.e. those not claimed by a
+    // deeper one.
+    pub fn governed_by(&self, subdirectory: &str) -> Result<Vec<PathBuf>, RadomskoError> {
+        let directory = self.lexical_path_for(subdirectory)?;
+        if !directory.is_dir() {
+            return Ok(Vec::new());
+        }
+        let directory = directory
+            .canonicalize()
+            .map_err(io_error_at("resolve", &directory))?;
+        Ok(self
+            .passwords_under(subdirectory)?
+            .into_iter()
+            .filter(|password| match self.recipients_file_for(password) {
+                Some((_, governing)) => {
+                    let governing_directory = governing.parent().unwrap();
+                    governing_directory == directory || !governing_directory.starts_with(&directory)
+                }
+                None => true,
+            })
+            .collect())
+    }
+
+    // Writes `recipients` into the recipients file for `format` in
+    // `subdirectory`, creating it if necessary. Any recipients file for
+    // the other format in `subdirectory` is removed.
+    //
+    // Returns the recipients file written.
+    pub fn set_recipients(
+        &self,
+        subdirectory: &str,
+        format: EntryFormat,
+        recipients: &[String],
+    ) -> Result<PathBuf, RadomskoError> {
+        let directory = self.lexical_path_for(subdirectory)?;
+        let recipients_file = directory.join(format.recipients_file());
+        self.create_directories_for(&recipients_file)?;
+        let mut contents = recipients.join("\n");
+        contents.push('\n');
+        std::fs::write(&recipients_file, contents)
+            .map_err(io_error_at("write", &recipients_file))?;
+        for other in [EntryFormat::Gpg, EntryFormat::Age] {
+            let other_file = directory.join(other.recipients_file());
+            if other != format && other_file.is_file() {
+                std::fs::remove_file(&other_file).map_err(io_error_at("remove", &other_file))?;
+            }
+        }
+        Ok(recipients_file)
+    }
+
+    // Like `recipients_for()`, but fails if no recipients file applies
+    // to `path` or if the one that does lists no recipients.
+    pub fn required_recipients_for(&self, path: &Path) -> Result<Vec<String>, RadomskoError> {
+        match self.recipients_for(path)? {
+            Some(recipients) if !recipients.is_empty() => Ok(recipients),
+            _ => Err(RadomskoError::NoRecipients(format!(
+                "no recipients in any .gpg-id or .age-recipients between {} and {}",
+                path.display(),
+                self.root.display()
+            ))),
+        }
+    }
+
+    // Plans a copy or move of the password or directory named `source`
+    // to `destination`. Nothing is written; the directories that the
+    // destination needs are left for `create_directories_for()`.
+    //
+    // Returns pairs of paths to copy from and to. When `source` is a
+    // directory, its recipients files are listed before its passwords so
+    // that they are in place before the passwords are transferred.
+    //
+    // As with `mv(1)`, an existing directory or a trailing slash in
+    // `destination` places `source` inside of it.
+    pub fn plan_transfer(
+        &self,
+        source: &str,
+        destination: &str,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, RadomskoError> {
+        let source_directory = self.path_for_impl(source, false)?;
+        let mut destination_name = PathBuf::from(destination);
+        if destination.ends_with('/') || self.lexical_path_for(destination)?.is_dir() {
+            destination_name.push(source_directory.file_name().unwrap());
+        }
+
+        if !source_directory.is_dir() {
+            let password = self.path_for(source)?;
+            if !password.is_file() {
+                return Err(RadomskoError::NotFound(source.to_string()));
+            }
+            let target = self.transfer_destination_for(&destination_name)?;
+            if target == password {
+                return Err(RadomskoError::BadInput(format!(
+                    "``{}'' and ``{}'' are the same password",
+                    source, destination
+                )));
+            }
+            return Ok(vec![(password, target)]);
+        }
+
+        if source_directory == self.root {
+            return Err(RadomskoError::BadInput(
+                "refusing to transfer the password store root".to_string(),
+            ));
+        }
+        let destination_directory = self.planned_path_for(destination_name.to_str().unwrap())?;
+        if destination_directory.starts_with(&source_directory) {
+            return Err(RadomskoError::BadInput(format!(
+                "cannot transfer ``{}'' into itself",
+                source
+            )));
+        }
+
+        let mut sources: Vec<PathBuf> = walkdir::WalkDir::new(&source_directory)
+            .into_iter()
+            .filter_map(ok_dirent_as_pathbuf)
+            .filter(|e| is_password_file(e) || is_recipients_file(e))
+            .collect();
+        sources.sort_by_key(|e| (!is_recipients_file(e), e.clone()));
+
+        let mut result: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for path in sources {
+            let relative = path.strip_prefix(&source_directory).unwrap();
+            let target = if is_recipients_file(&path) {
+                self.planned_path_for(destination_name.join(relative).to_str().unwrap())?
+            } else {
+                let mut name = relative.to_path_buf();
+                name.set_extension("");
+                self.transfer_destination_for(&destination_name.join(name))?
+            };
+            result.push((path, target));
+        }
+        Ok(result)
+    }
+
+    // Aids `plan_transfer()` by returning the path that password `name`
+    // is to be transferred to.
+    fn transfer_destination_for(&self, name: &Path) -> Result<PathBuf, RadomskoError> {
+        let path = self.planned_path_for(name.to_str().unwrap())?;
+        Ok(self.with_password_extension(path))
+    }
+
+    // Returns the path of the password or directory named `name` that
+    // `remove()` would remove, failing as it would without touching
+    // anything. Directories are only allowed if `recursive` is set.
+    pub fn removal_target(&self, name: &str, recursive: bool) -> Result<PathBuf, RadomskoError> {
+        let directory = self.path_for_impl(name, false)?;
+        if directory.is_dir() {
+            if directory == self.root {
+                return Err(RadomskoError::BadInput(
+                    "refusing to remove the password store root".to_string(),
+                ));
+            } else if !recursive {
+                return Err(RadomskoError::BadInput(format!(
+                    "``{}'' is a directory",
+                    name
+                )));
+            }
+            return Ok(directory);
+        }
+        let password = self.path_for(name)?;
+        if !password.is_file() {
+            return Err(RadomskoError::NotFound(name.to_string()));
+        }
+        Ok(password)
+    }
+
+    // Removes the password or directory named `name` from the password
+    // store and returns the path that was removed.
+    //
+    // *    Directories are only removed if `recursive` is set.
+    // *    Directories left empty by the removal are pruned, up to but
+    //      not including `root`.
+    pub fn remove(&self, name: &str, recursive: bool) -> Result<PathBuf, RadomskoError> {
+        let removed = self.removal_target(name, recursive)?;
+        if removed.is_dir() {
+            std::fs::remove_dir_all(&removed).map_err(io_error_at("remove", &removed))?;
+        } else {
+            std::fs::remove_file(&removed).map_err(io_error_at("remove", &removed))?;
+        }
+
+        self.prune_empty_directories_above(&removed)?;
+        Ok(removed)
+    }
+
+    // Removes empty directories containing `path`, stopping at the
+    // first nonempty one or at `root`.
+    pub fn prune_empty_directories_above(&self, path: &Path) -> Result<(), RadomskoError> {
+        let mut current = path.parent();
+        while let Some(directory) = current {
+            if directory == self.root || !directory.starts_with(&self.root) {
+                break;
+            }
+            if std::fs::read_dir(directory)
+                .map_err(io_error_at("read", directory))?
+                .next()
+                .is_some()
+            {
+                break;
+            }
+            std::fs::remove_dir(directory).map_err(io_error_at("remove", directory))?;
+            current = directory.parent();
+        }
+        Ok(())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn colorize_display(&self) -> bool {
+        self.colorize_display
+    }
+
     // Borrows a `password_path` and returns its symbolic "name."
-    fn symbolic_name_for(&self, password_path: &Path) -> PathBuf {
+    pub fn symbolic_name_for(&self, password_path: &Path) -> PathBuf {
         assert!(password_path.is_absolute());
         assert!(password_path.starts_with(&self.root));
 
@@ -144,28 +727,27 @@ impl PasswordStoreInterface {
     ) -> Result<Vec<PathBuf>, RadomskoError> {
         let path = self.path_for_impl(subdirectory, false)?;
         if !path.is_dir() {
-            return Err(RadomskoError::NotFound);
+            return Err(RadomskoError::NotFound(subdirectory.to_string()));
         }
 
         let mut result: Vec<PathBuf> = walkdir::WalkDir::new(path)
             .into_iter()
-            .filter_map(|e| ok_dirent_as_pathbuf(e))
-            .filter(|e| is_gpg_file(e))
+            .filter_map(ok_dirent_as_pathbuf)
+            .filter(|e| is_password_file(e))
             .collect();
         result.sort();
         Ok(result)
     }
 
-    // Aids `draw_tree()` when a `search_term` is specified.
+    // Aids `draw_tree()` when a `search` is specified.
     //
-    // Returns a sorted Vec of passwords matching `search_term`.
-    fn walk_tree_for_search_term(&self, search_term: &str) -> Vec<PathBuf> {
+    // Returns a sorted Vec of passwords whose names match `search`.
+    fn walk_tree_for_search(&self, search: &SearchTerms) -> Vec<PathBuf> {
         let mut result: Vec<PathBuf> = walkdir::WalkDir::new(&self.root)
             .into_iter()
-            .filter_map(|e| ok_dirent_as_pathbuf(e))
+            .filter_map(ok_dirent_as_pathbuf)
             .filter(|e| {
-                is_gpg_file(e)
-                    && dirent_matches_search_term(&self.symbolic_name_for(e), search_term)
+                is_password_file(e) && search.matches(self.symbolic_name_for(e).to_str().unwrap())
             })
             .collect();
         result.sort();
@@ -178,117 +760,185 @@ impl PasswordStoreInterface {
     fn walk_tree(&self) -> Vec<PathBuf> {
         let mut result: Vec<PathBuf> = walkdir::WalkDir::new(&self.root)
             .into_iter()
-            .filter_map(|e| ok_dirent_as_pathbuf(e))
-            .filter(|e| is_gpg_file(e))
+            .filter_map(ok_dirent_as_pathbuf)
+            .filter(|e| is_password_file(e))
             .collect();
         result.sort();
         result
     }
 
+    // Returns a sorted Vec of passwords under relative path
+    // `subdirectory`, or of all passwords if `subdirectory` is empty.
+    pub fn passwords_under(&self, subdirectory: &str) -> Result<Vec<PathBuf>, RadomskoError> {
+        match subdirectory.is_empty() {
+            true => Ok(self.walk_tree()),
+            false => self.walk_tree_for_subdirectory(subdirectory),
+        }
+    }
+
+    // Returns the names completing `prefix` in sorted order: those of
+    // passwords, and those of directories (with a trailing slash) one
+    // level further down. Only directories are given if
+    // `directories_only` is set.
+    pub fn completions_for(&self, prefix: &str, directories_only: bool) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        for password in self.walk_tree() {
+            let name = self.symbolic_name_for(&password);
+            let name = name.to_str().unwrap();
+            let rest = match name.strip_prefix(prefix) {
+                Some(rest) => rest,
+                None => continue,
+            };
+            let completion = match rest.find('/') {
+                Some(slash) => format!("{}{}", prefix, &rest[..=slash]),
+                None if directories_only => continue,
+                None => name.to_owned(),
+            };
+            // `walk_tree()` keeps the passwords in each directory together.
+            if result.last() != Some(&completion) {
+                result.push(completion);
+            }
+        }
+        result
+    }
+
     // Aids `draw_tree()` by laying out one branch of the tree.
     //
     // Accepts the `previous` password drawn in the tree and the
-    // `current` password to draw.
-    fn draw_tree_branch(&self, previous: &Path, current: &Path) -> Vec<String> {
+    // `current` password to draw, whose parts matching `search` (if
+    // given) are highlighted.
+    fn draw_tree_branch(
+        &self,
+        previous: &Path,
+        current: &Path,
+        search: Option<&SearchTerms>,
+    ) -> Vec<String> {
         let symbolic_previous = self.symbolic_name_for(previous);
         let symbolic_current = self.symbolic_name_for(current);
+        let name = symbolic_current.to_str().unwrap();
+        let matched = match search {
+            Some(search) if self.colorize_display => search.ranges_in(name),
+            _ => Vec::new(),
+        };
 
-        let mut previous_components = symbolic_previous.iter();
-        let mut current_components = symbolic_current.iter();
-        let mut indent: usize = 0;
+        // Skips over common ancestry with `previous`, since we only
+        // want to draw novel parts of the branch. The leaf is always
+        // drawn, even if `previous` was a directory of the same name.
+        let components: Vec<&str> = name.split('/').collect();
+        let common = symbolic_previous
+            .iter()
+            .zip(symbolic_current.iter())
+            .take_while(|(previous, current)| previous == current)
+            .count()
+            .min(components.len() - 1);
 
         let mut result: Vec<String> = Vec::new();
-        let mut previous_match: Option<&std::ffi::OsStr> = previous_components.next();
-        let mut current_match: Option<&std::ffi::OsStr> = current_components.next();
-
-        // Seeks forward along `current` to ignore common ancestry with
-        // `previous`, since we only want to draw novel parts of the
-        // branch.
-        while previous_match.is_some()
-            && current_match.is_some()
-            && previous_match.unwrap() == current_match.unwrap()
-        {
-            previous_match = previous_components.next();
-            current_match = current_components.next();
-            indent += 1;
-        }
-
-        // Push the first unique component of the `current` branch.
-        result.push(tree_branch_with_indent(
-            current_match.unwrap(),
-            indent,
-            self.colorize_display,
-        ));
-
-        // Push the remaining unique components of the `current` branch.
-        for remainder in current_components {
-            indent += 1;
-            result.push(tree_branch_with_indent(
-                remainder,
-                indent,
-                self.colorize_display,
-            ));
-        }
-
-        // If colorization is enabled, `result` consists solely of
-        // colorified entries; however, we want the leaf values to be
-        // monochrome.
-        if self.colorize_display {
-            result.pop();
+        let mut offset: usize = components[..common].iter().map(|c| c.len() + 1).sum();
+        for (indent, component) in components.iter().enumerate().skip(common) {
+            let end = offset + component.len();
+            let component_matched: Vec<Range<usize>> = matched
+                .iter()
+                .filter(|range| range.start < end && range.end > offset)
+                .map(|range| range.start.max(offset) - offset..range.end.min(end) - offset)
+                .collect();
+            // Only directories are colorized; leaves stay monochrome.
+            let is_directory = indent + 1 < components.len();
             result.push(tree_branch_with_indent(
-                symbolic_current.iter().last().unwrap(),
+                component,
                 indent,
-                false,
+                self.colorize_display && is_directory,
+                &component_matched,
             ));
+            offset = end + 1;
         }
         result
     }
 
     // Aids `draw_tree()` by laying out the actual tree.
-    fn draw_tree_impl(&self, tree: Vec<PathBuf>) -> String {
-        if tree.len() == 0 {
+    fn draw_tree_impl(&self, tree: Vec<PathBuf>, search: Option<&SearchTerms>) -> String {
+        if tree.is_empty() {
             return "".to_owned();
         }
         let mut result: Vec<String> = Vec::new();
         let mut prev = self.root.as_path();
 
         for password in tree.iter() {
-            result.extend(self.draw_tree_branch(prev, password));
+            result.extend(self.draw_tree_branch(prev, password, search));
             prev = password;
         }
 
         result.join("\n")
     }
 
+    // Aids `draw_tree()` and `list()` by picking the passwords to
+    // show, in sorted order; see `draw_tree()` for the arguments.
+    fn listed_passwords(
+        &self,
+        subdirectory: &str,
+        search: Option<&SearchTerms>,
+    ) -> Result<Vec<PathBuf>, RadomskoError> {
+        assert!(subdirectory.is_empty() || search.is_none());
+
+        if !subdirectory.is_empty() {
+            self.walk_tree_for_subdirectory(subdirectory)
+        } else if let Some(search) = search {
+            Ok(self.walk_tree_for_search(search))
+        } else {
+            Ok(self.walk_tree())
+        }
+    }
+
     // Returns the human-readable string representation of the password
     // store, drawn as a tree.
     //
     // Arguments:
     // *    `subdirectory` - if nonempty, restricts return value to
     //          branches under relative path `subdirectory`.
-    // *    `search_term` - if nonempty, restricts return value to
-    //          branches that match `search_term`.
+    // *    `search` - if given, restricts return value to branches
+    //          whose names match `search`, highlighting the matches.
     //
-    // `subdirectory` is used with the "show" command while
-    // `search_term` is used with the "find" command. Therefore, these
-    // arguments are mutually exclusive.
+    // `subdirectory` is used with the "show" command while `search` is
+    // used with the "find" command. Therefore, these arguments are
+    // mutually exclusive.
     pub fn draw_tree(
         &self,
         subdirectory: &str,
-        search_term: &str,
+        search: Option<&SearchTerms>,
     ) -> Result<String, RadomskoError> {
-        assert!(!(!subdirectory.is_empty() && !search_term.is_empty()));
+        let tree = self.listed_passwords(subdirectory, search)?;
+        Ok(self.draw_tree_impl(tree, search))
+    }
 
-        let tree: Vec<PathBuf>;
-        if !subdirectory.is_empty() {
-            tree = self.walk_tree_for_subdirectory(subdirectory)?;
-        } else if !search_term.is_empty() {
-            tree = self.walk_tree_for_search_term(search_term);
-        } else {
-            tree = self.walk_tree();
+    // Like `draw_tree()`, but lays the passwords out in `format`. The
+    // result ends in a newline (or NUL) where it is not empty.
+    pub fn list(
+        &self,
+        subdirectory: &str,
+        search: Option<&SearchTerms>,
+        format: ListingFormat,
+    ) -> Result<String, RadomskoError> {
+        if format == ListingFormat::Tree {
+            return Ok(format!("{}\n", self.draw_tree(subdirectory, search)?));
         }
-
-        Ok(self.draw_tree_impl(tree))
+        let names: Vec<String> = self
+            .listed_passwords(subdirectory, search)?
+            .iter()
+            .map(|password| {
+                self.symbolic_name_for(password)
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        Ok(match format {
+            ListingFormat::Tree => unreachable!(),
+            ListingFormat::List => names.iter().map(|name| format!("{}\n", name)).collect(),
+            ListingFormat::Json => format!(
+                "{}\n",
+                serde_json::to_string_pretty(&nest_names(&names)).unwrap()
+            ),
+            ListingFormat::Null => names.iter().map(|name| format!("{}\0", name)).collect(),
+        })
     }
 }
 
@@ -310,6 +960,14 @@ mod tests {
         PasswordStoreInterface::new(test_data_path(subdir).to_str().unwrap(), false).unwrap()
     }
 
+    fn search_terms(terms: &[&str]) -> SearchTerms {
+        let patterns = terms
+            .iter()
+            .map(|term| crate::search::compile_pattern(term, false, false).unwrap())
+            .collect();
+        SearchTerms::new(patterns, false)
+    }
+
     #[test]
     fn password_store_interface_requires_existing_root() {
         let err =
@@ -318,6 +976,22 @@ mod tests {
         assert!(matches!(err, RadomskoError::IoError { .. }));
     }
 
+    #[test]
+    fn password_store_interface_resolves_symlinked_root() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let link = tmp_dir.path().join("link");
+        std::os::unix::fs::symlink(test_data_path("path-for-basic"), &link).unwrap();
+        let interface = PasswordStoreInterface::new(link.to_str().unwrap(), false).unwrap();
+        assert_eq!(
+            interface.path_for("hello-there").unwrap(),
+            test_data_path("path-for-basic/hello-there.gpg")
+        );
+        assert_eq!(
+            interface.path_for("general/kenobi/hello-there").unwrap(),
+            test_data_path("path-for-basic/general/kenobi/hello-there.gpg")
+        );
+    }
+
     #[test]
     fn path_for_basic() {
         let path = password_store_interface("path-for-basic")
@@ -342,7 +1016,10 @@ mod tests {
         let err = password_store_interface("path-for-basic")
             .path_for("general/grievous/whats-the-situation")
             .unwrap_err();
-        assert!(matches!(err, RadomskoError::IoError { .. }));
+        assert_eq!(
+            err,
+            RadomskoError::NotFound("general/grievous/whats-the-situation".to_string())
+        );
     }
 
     #[test]
@@ -370,7 +1047,12 @@ mod tests {
         let err = password_store_interface("path-for-basic")
             .path_for("general/kenobi/../../../path-for-basic-escape-path/klaus")
             .unwrap_err();
-        assert!(matches!(err, RadomskoError::IoError { .. }));
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+        assert!(err.to_string().contains(
+            test_data_path("path-for-basic-escape-path/klaus")
+                .to_str()
+                .unwrap()
+        ));
     }
 
     #[test]
@@ -382,11 +1064,439 @@ mod tests {
         );
     }
 
+    // Returns a scratch password store populated with empty passwords
+    // at each of the relative `passwords`. The store is deleted when the
+    // returned `TempDir` is dropped.
+    fn scratch_password_store(passwords: &[&str]) -> (tempfile::TempDir, PasswordStoreInterface) {
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("password-store-fixture-")
+            .tempdir()
+            .unwrap();
+        let root = tmp_dir.path().canonicalize().unwrap();
+        for password in passwords {
+            let path = root.join(password);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let interface = PasswordStoreInterface::new(root.to_str().unwrap(), false).unwrap();
+        (tmp_dir, interface)
+    }
+
+    #[test]
+    fn path_for_disallows_parent_as_leaf() {
+        let err = password_store_interface("path-for-basic")
+            .path_for("general/..")
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn remove_password() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a.gpg", "b.gpg"]);
+        let removed = interface.remove("a", false).unwrap();
+        assert_eq!(removed, interface.root.join("a.gpg"));
+        assert!(!removed.exists());
+        assert!(interface.root.join("b.gpg").is_file());
+    }
+
+    #[test]
+    fn remove_nonexistent_password() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a.gpg"]);
+        assert_eq!(
+            interface.remove("b", false).unwrap_err(),
+            RadomskoError::NotFound("b".to_string())
+        );
+    }
+
+    #[test]
+    fn remove_prunes_empty_directories() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b/c.gpg", "a/d.gpg"]);
+        interface.remove("a/b/c", false).unwrap();
+        assert!(!interface.root.join("a/b").exists());
+        assert!(interface.root.join("a/d.gpg").is_file());
+
+        interface.remove("a/d", false).unwrap();
+        assert!(!interface.root.join("a").exists());
+        assert!(interface.root.is_dir());
+    }
+
+    #[test]
+    fn remove_directory_requires_recursive() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg"]);
+        let err = interface.remove("a", false).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+        assert!(interface.root.join("a/b.gpg").is_file());
+    }
+
+    #[test]
+    fn remove_directory_recursively() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b/c.gpg", "a/d.gpg", "e.gpg"]);
+        interface.remove("a", true).unwrap();
+        assert!(!interface.root.join("a").exists());
+        assert!(interface.root.join("e.gpg").is_file());
+    }
+
+    #[test]
+    fn remove_disallows_root() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg"]);
+        let err = interface.remove("a/../a/..", true).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+        let err = interface.remove(".", true).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+        assert!(interface.root.join("a/b.gpg").is_file());
+    }
+
+    #[test]
+    fn remove_disallows_escaping_paths() {
+        let (_outer_tmp_dir, outer) = scratch_password_store(&["store/a.gpg", "victim.gpg"]);
+        let interface =
+            PasswordStoreInterface::new(outer.root.join("store").to_str().unwrap(), false).unwrap();
+        let err = interface.remove("../victim", false).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+        assert!(outer.root.join("victim.gpg").is_file());
+    }
+
+    #[test]
+    fn recipients_for_finds_nearest_gpg_id() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b/c.gpg", "d.gpg"]);
+        std::fs::write(interface.root.join(".gpg-id"), "root@example.com\n").unwrap();
+        std::fs::write(
+            interface.root.join("a/.gpg-id"),
+            "# team keys\nzed@example.com\n\nbob@example.com # bob\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            interface
+                .recipients_for(&interface.root.join("a/b/c.gpg"))
+                .unwrap(),
+            Some(vec![
+                "bob@example.com".to_string(),
+                "zed@example.com".to_string()
+            ])
+        );
+        assert_eq!(
+            interface
+                .recipients_for(&interface.root.join("d.gpg"))
+                .unwrap(),
+            Some(vec!["root@example.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn recipients_for_without_gpg_id() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg"]);
+        assert_eq!(
+            interface
+                .recipients_for(&interface.root.join("a/b.gpg"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn recipients_override_replaces_gpg_id() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg", "c.gpg"]);
+        std::fs::write(interface.root.join("a/.gpg-id"), "bob@example.com\n").unwrap();
+        let interface = interface.with_recipients_override(vec!["0xDEADBEEF".to_string()]);
+        for password in ["a/b.gpg", "c.gpg"] {
+            assert_eq!(
+                interface
+                    .required_recipients_for(&interface.root.join(password))
+                    .unwrap(),
+                vec!["0xDEADBEEF".to_string()]
+            );
+        }
+    }
+
+    #[test]
+    fn required_recipients_for_fails_without_gpg_id() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg"]);
+        let err = interface
+            .required_recipients_for(&interface.root.join("a/b.gpg"))
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::NoRecipients { .. }));
+    }
+
+    #[test]
+    fn required_recipients_for_fails_with_empty_gpg_id() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg"]);
+        std::fs::write(interface.root.join("a/.gpg-id"), "# nobody\n").unwrap();
+        let err = interface
+            .required_recipients_for(&interface.root.join("a/b.gpg"))
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::NoRecipients { .. }));
+    }
+
+    #[test]
+    fn set_recipients_in_root() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a.gpg", "b/c.gpg"]);
+        let written = interface
+            .set_recipients("", EntryFormat::Gpg, &["alice@example.com".to_string()])
+            .unwrap();
+        assert_eq!(written, interface.root.join(".gpg-id"));
+        assert_eq!(
+            std::fs::read_to_string(interface.root.join(".gpg-id")).unwrap(),
+            "alice@example.com\n"
+        );
+        assert_eq!(
+            interface.governed_by("").unwrap(),
+            vec![interface.root.join("a.gpg"), interface.root.join("b/c.gpg")]
+        );
+    }
+
+    #[test]
+    fn set_recipients_skips_deeper_gpg_id() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg", "a/c/d.gpg"]);
+        std::fs::write(interface.root.join("a/c/.gpg-id"), "bob@example.com\n").unwrap();
+        assert_eq!(
+            interface.governed_by("a").unwrap(),
+            vec![interface.root.join("a/b.gpg")]
+        );
+        interface
+            .set_recipients(
+                "a",
+                EntryFormat::Gpg,
+                &[
+                    "alice@example.com".to_string(),
+                    "carol@example.com".to_string(),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            interface.governed_by("a").unwrap(),
+            vec![interface.root.join("a/b.gpg")]
+        );
+        assert_eq!(
+            interface
+                .recipients_for(&interface.root.join("a/b.gpg"))
+                .unwrap(),
+            Some(vec![
+                "alice@example.com".to_string(),
+                "carol@example.com".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn set_recipients_creates_subdirectory() {
+        let (_tmp_dir, interface) = scratch_password_store(&[]);
+        assert!(interface.governed_by("a/b").unwrap().is_empty());
+        interface
+            .set_recipients("a/b", EntryFormat::Gpg, &["alice@example.com".to_string()])
+            .unwrap();
+        assert!(interface.root.join("a/b/.gpg-id").is_file());
+    }
+
+    #[test]
+    fn set_age_recipients_replaces_gpg_id() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/.gpg-id", "a/b.gpg", "c.gpg"]);
+        interface
+            .set_recipients("a", EntryFormat::Age, &["age1alice".to_string()])
+            .unwrap();
+        assert_eq!(
+            interface.governed_by("a").unwrap(),
+            vec![interface.root.join("a/b.gpg")]
+        );
+        assert!(!interface.root.join("a/.gpg-id").exists());
+        assert_eq!(
+            std::fs::read_to_string(interface.root.join("a/.age-recipients")).unwrap(),
+            "age1alice\n"
+        );
+    }
+
+    #[test]
+    fn age_recipients_govern_format() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b/c.gpg", "d.gpg"]);
+        std::fs::write(interface.root.join(".gpg-id"), "root@example.com\n").unwrap();
+        std::fs::write(interface.root.join("a/.gpg-id"), "bob@example.com\n").unwrap();
+        std::fs::write(interface.root.join("a/.age-recipients"), "age1bob\n").unwrap();
+        let interface = interface.with_recipients_override(vec!["0xDEADBEEF".to_string()]);
+
+        let c = interface.root.join("a/b/c.gpg");
+        assert_eq!(interface.format_for(&c), EntryFormat::Age);
+        assert_eq!(
+            interface.recipients_for(&c).unwrap(),
+            Some(vec!["age1bob".to_string()])
+        );
+        let d = interface.root.join("d.gpg");
+        assert_eq!(interface.format_for(&d), EntryFormat::Gpg);
+        assert_eq!(
+            interface.recipients_for(&d).unwrap(),
+            Some(vec!["0xDEADBEEF".to_string()])
+        );
+
+        let listed = ["bob".to_string(), "alice".to_string(), "bob".to_string()];
+        assert_eq!(
+            interface.effective_recipients(EntryFormat::Age, &listed),
+            vec!["alice", "bob"]
+        );
+        assert_eq!(
+            interface.effective_recipients(EntryFormat::Gpg, &listed),
+            vec!["0xDEADBEEF"]
+        );
+    }
+
+    #[test]
+    fn path_for_mixed_formats() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg", "a/c.age", "d.age"]);
+        std::fs::write(interface.root.join("a/.age-recipients"), "age1bob\n").unwrap();
+        assert_eq!(
+            interface.path_for("a/b").unwrap(),
+            interface.root.join("a/b.gpg")
+        );
+        assert_eq!(
+            interface.path_for("a/c").unwrap(),
+            interface.root.join("a/c.age")
+        );
+        assert_eq!(
+            interface.path_for("a/new").unwrap(),
+            interface.root.join("a/new.age")
+        );
+        assert_eq!(
+            interface.path_for("d").unwrap(),
+            interface.root.join("d.age")
+        );
+        assert_eq!(
+            interface.path_for("new").unwrap(),
+            interface.root.join("new.gpg")
+        );
+        assert_eq!(
+            interface.passwords_under("").unwrap(),
+            vec![
+                interface.root.join("a/b.gpg"),
+                interface.root.join("a/c.age"),
+                interface.root.join("d.age")
+            ]
+        );
+    }
+
+    #[test]
+    fn create_root() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("a/b");
+        PasswordStoreInterface::create_root(root.to_str().unwrap()).unwrap();
+        assert!(PasswordStoreInterface::new(root.to_str().unwrap(), false).is_ok());
+    }
+
+    #[test]
+    fn plan_transfer_renames_password() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a.gpg"]);
+        assert_eq!(
+            interface.plan_transfer("a", "b/c/d").unwrap(),
+            vec![(
+                interface.root.join("a.gpg"),
+                interface.root.join("b/c/d.gpg")
+            )]
+        );
+        assert!(!interface.root.join("b").exists());
+    }
+
+    #[test]
+    fn plan_transfer_into_existing_directory() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a.gpg", "b/c.gpg"]);
+        assert_eq!(
+            interface.plan_transfer("a", "b").unwrap(),
+            vec![(interface.root.join("a.gpg"), interface.root.join("b/a.gpg"))]
+        );
+        assert_eq!(
+            interface.plan_transfer("a", "d/").unwrap(),
+            vec![(interface.root.join("a.gpg"), interface.root.join("d/a.gpg"))]
+        );
+    }
+
+    #[test]
+    fn plan_transfer_directory() {
+        let (_tmp_dir, interface) =
+            scratch_password_store(&["a/b/c.gpg", "a/d.gpg", "a/.gpg-id", "e.gpg"]);
+        assert_eq!(
+            interface.plan_transfer("a", "f").unwrap(),
+            vec![
+                (
+                    interface.root.join("a/.gpg-id"),
+                    interface.root.join("f/.gpg-id")
+                ),
+                (
+                    interface.root.join("a/b/c.gpg"),
+                    interface.root.join("f/b/c.gpg")
+                ),
+                (
+                    interface.root.join("a/d.gpg"),
+                    interface.root.join("f/d.gpg")
+                ),
+            ]
+        );
+        assert!(!interface.root.join("f").exists());
+    }
+
+    #[test]
+    fn plan_transfer_disallows_directory_into_itself() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a/b.gpg"]);
+        let err = interface.plan_transfer("a", "a/c").unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn plan_transfer_disallows_nonexistent_source() {
+        let (_tmp_dir, interface) = scratch_password_store(&["a.gpg"]);
+        assert_eq!(
+            interface.plan_transfer("b", "c").unwrap_err(),
+            RadomskoError::NotFound("b".to_string())
+        );
+    }
+
+    #[test]
+    fn plan_transfer_disallows_escaping_destination() {
+        let (_outer_tmp_dir, outer) = scratch_password_store(&["store/a.gpg"]);
+        let interface =
+            PasswordStoreInterface::new(outer.root.join("store").to_str().unwrap(), false).unwrap();
+        let err = interface.plan_transfer("a", "../../b/c").unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+        assert!(!outer.root.join("b").exists());
+    }
+
+    #[test]
+    fn plan_transfer_disallows_destination_through_symlink() {
+        let (_outer_tmp_dir, outer) = scratch_password_store(&["store/a.gpg", "elsewhere/b.gpg"]);
+        std::os::unix::fs::symlink(outer.root.join("elsewhere"), outer.root.join("store/link"))
+            .unwrap();
+        let interface =
+            PasswordStoreInterface::new(outer.root.join("store").to_str().unwrap(), false).unwrap();
+        let err = interface.plan_transfer("a", "link/c/d").unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn completions_stop_at_directories() {
+        let (_tmp_dir, interface) = scratch_password_store(&[
+            "email.gpg",
+            "web/example.com.gpg",
+            "web/shop/a.gpg",
+            "web/shop/b.age",
+            "wiki.age",
+        ]);
+        assert_eq!(
+            interface.completions_for("", false),
+            vec!["email", "web/", "wiki"]
+        );
+        assert_eq!(interface.completions_for("w", false), vec!["web/", "wiki"]);
+        assert_eq!(
+            interface.completions_for("web/", false),
+            vec!["web/example.com", "web/shop/"]
+        );
+        assert_eq!(
+            interface.completions_for("web/shop/", false),
+            vec!["web/shop/a", "web/shop/b"]
+        );
+        assert_eq!(interface.completions_for("", true), vec!["web/"]);
+        assert!(interface.completions_for("x", false).is_empty());
+    }
+
     #[test]
     fn draw_tree_with_embedded_folders() {
         let interface = password_store_interface("draw-tree-with-embedded-folders");
         assert_eq!(
-            interface.draw_tree("", "").unwrap(),
+            interface.draw_tree("", None).unwrap(),
             indoc! {r#"
             *   a
                 *   b
@@ -400,7 +1510,7 @@ mod tests {
     fn draw_tree_with_files() {
         let interface = password_store_interface("draw-tree-with-files");
         assert_eq!(
-            interface.draw_tree("", "").unwrap(),
+            interface.draw_tree("", None).unwrap(),
             indoc! {r#"
             *   a
             *   b"#}
@@ -411,7 +1521,7 @@ mod tests {
     fn draw_tree_with_folders() {
         let interface = password_store_interface("draw-tree-with-folders");
         assert_eq!(
-            interface.draw_tree("", "").unwrap(),
+            interface.draw_tree("", None).unwrap(),
             indoc! {r#"
             *   a
             *   b
@@ -429,7 +1539,7 @@ mod tests {
     fn draw_tree_specifying_subdirectory() {
         let interface = password_store_interface("draw-tree-with-folders");
         assert_eq!(
-            interface.draw_tree("b", "").unwrap(),
+            interface.draw_tree("b", None).unwrap(),
             indoc! {r#"
             *   b
                 *   a
@@ -441,7 +1551,7 @@ mod tests {
     fn draw_tree_specifying_subsubdirectory() {
         let interface = password_store_interface("draw-tree-with-embedded-folders");
         assert_eq!(
-            interface.draw_tree("a/b", "").unwrap(),
+            interface.draw_tree("a/b", None).unwrap(),
             indoc! {r#"
             *   a
                 *   b
@@ -453,7 +1563,7 @@ mod tests {
     fn draw_tree_specifying_subdirectory_with_deeper_subdirectory() {
         let interface = password_store_interface("draw-tree-with-embedded-folders");
         assert_eq!(
-            interface.draw_tree("a", "").unwrap(),
+            interface.draw_tree("a", None).unwrap(),
             indoc! {r#"
             *   a
                 *   b
@@ -465,8 +1575,9 @@ mod tests {
     #[test]
     fn draw_tree_specifying_search_term() {
         let interface = password_store_interface("draw-tree-with-folders");
+        let search = search_terms(&["a"]);
         assert_eq!(
-            interface.draw_tree("", "a").unwrap(),
+            interface.draw_tree("", Some(&search)).unwrap(),
             indoc! {r#"
             *   a
             *   b
@@ -475,4 +1586,93 @@ mod tests {
                 *   a"#}
         );
     }
+
+    #[test]
+    fn draw_tree_highlights_search_matches() {
+        let interface = PasswordStoreInterface::new(
+            test_data_path("draw-tree-with-folders").to_str().unwrap(),
+            true,
+        )
+        .unwrap();
+        let search = search_terms(&["d/"]);
+        assert_eq!(
+            interface.draw_tree("", Some(&search)).unwrap(),
+            [
+                format!("{}{}", highlight("*   "), highlight_underlined("d")),
+                format!("    *   {}", "a"),
+                format!("    *   {}", "b"),
+            ]
+            .join("\n")
+        );
+
+        let search = search_terms(&["a"]);
+        let drawn = interface.draw_tree("", Some(&search)).unwrap();
+        assert_eq!(
+            drawn.lines().next().unwrap(),
+            format!("*   {}", highlight("a"))
+        );
+    }
+
+    #[test]
+    fn list_formats() {
+        let interface = password_store_interface("draw-tree-with-folders");
+        assert_eq!(
+            interface.list("b", None, ListingFormat::List).unwrap(),
+            "b/a\nb/b\n"
+        );
+        let search = search_terms(&["a"]);
+        assert_eq!(
+            interface
+                .list("", Some(&search), ListingFormat::Null)
+                .unwrap(),
+            "a\0b/a\0d/a\0"
+        );
+        assert_eq!(
+            interface.list("b", None, ListingFormat::Tree).unwrap(),
+            format!("{}\n", interface.draw_tree("b", None).unwrap())
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&interface.list("d", None, ListingFormat::Json).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "type": "directory",
+                "name": "d",
+                "entries": [
+                    {"type": "entry", "name": "a", "path": "d/a"},
+                    {"type": "entry", "name": "b", "path": "d/b"},
+                ],
+            }])
+        );
+    }
+
+    #[test]
+    fn nest_names_groups_by_directory() {
+        let names: Vec<String> = ["a/b/c", "a/b", "a/d", "e"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let entry = |name: &str, path: &str| ListingNode::Entry {
+            name: name.to_string(),
+            path: path.to_string(),
+        };
+        assert_eq!(
+            nest_names(&names),
+            vec![
+                ListingNode::Directory {
+                    name: "a".to_string(),
+                    entries: vec![
+                        ListingNode::Directory {
+                            name: "b".to_string(),
+                            entries: vec![entry("c", "a/b/c")],
+                        },
+                        entry("b", "a/b"),
+                        entry("d", "a/d"),
+                    ],
+                },
+                entry("e", "e"),
+            ]
+        );
+    }
 }
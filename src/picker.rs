@@ -0,0 +1,268 @@
+// A fuzzy finder over entry names, drawn on the terminal with
+// crossterm so that `pick` needn't shell out to fzf.
+
+use std::io::{IsTerminal, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+
+use crate::enums::RadomskoError;
+
+// Characters after which a match counts as the start of a word.
+const WORD_SEPARATORS: &[char] = &['/', '-', '_', '.', '@', ' '];
+
+const MATCH_SCORE: i64 = 16;
+const WORD_START_BONUS: i64 = 32;
+const CONSECUTIVE_BONUS: i64 = 24;
+const GAP_PENALTY: i64 = 1;
+
+const FALLBACK_TERMINAL_SIZE: (u16, u16) = (80, 24);
+
+// Where and how well a query matched an entry name.
+#[derive(Debug, PartialEq)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    // The indices of the matching chars of the name.
+    pub positions: Vec<usize>,
+}
+
+fn chars_match(wanted: char, candidate: char) -> bool {
+    wanted.to_lowercase().eq(candidate.to_lowercase())
+}
+
+// Aids `fuzzy_match()` by matching `query` greedily against
+// `candidate`, given that its first char matches at `start`.
+fn fuzzy_match_from(query: &[char], candidate: &[char], start: usize) -> Option<FuzzyMatch> {
+    let mut score = 0;
+    let mut positions: Vec<usize> = Vec::new();
+    let mut next = start;
+    for wanted in query {
+        let found = (next..candidate.len()).find(|&i| chars_match(*wanted, candidate[i]))?;
+        score += MATCH_SCORE;
+        if found == 0 || WORD_SEPARATORS.contains(&candidate[found - 1]) {
+            score += WORD_START_BONUS;
+        }
+        match positions.last() {
+            Some(&previous) if previous + 1 == found => score += CONSECUTIVE_BONUS,
+            Some(&previous) => score -= GAP_PENALTY * (found - previous - 1) as i64,
+            None => (),
+        }
+        positions.push(found);
+        next = found + 1;
+    }
+    Some(FuzzyMatch { score, positions })
+}
+
+// Matches `query` against `candidate` without regard to case. Every
+// char of `query` (less whitespace) must appear in `candidate` in
+// order; runs of them and those starting a word (e.g. a path
+// component) score higher, while gaps between them score lower.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<FuzzyMatch> {
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    let first = match query.first() {
+        Some(first) => *first,
+        None => {
+            return Some(FuzzyMatch {
+                score: 0,
+                positions: Vec::new(),
+            })
+        }
+    };
+    (0..candidate.len())
+        .filter(|&start| chars_match(first, candidate[start]))
+        .filter_map(|start| fuzzy_match_from(&query, &candidate, start))
+        .max_by_key(|found| found.score)
+}
+
+// Returns the `names` matching `query`, best first. Ties go to the
+// shorter name, then to the one given first.
+pub fn filter<'a>(query: &str, names: &'a [String]) -> Vec<(&'a str, FuzzyMatch)> {
+    let mut result: Vec<(&str, FuzzyMatch)> = names
+        .iter()
+        .filter_map(|name| fuzzy_match(query, name).map(|found| (name.as_str(), found)))
+        .collect();
+    result.sort_by(|(a, a_match), (b, b_match)| {
+        b_match
+            .score
+            .cmp(&a_match.score)
+            .then(a.chars().count().cmp(&b.chars().count()))
+    });
+    result
+}
+
+// Puts the terminal back in order when dropped, however the picker
+// exits.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new(tty: &mut impl Write) -> std::io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(tty, terminal::EnterAlternateScreen)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(std::io::stderr(), terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Draws the prompt holding `query`, a count of the `matches` among
+// `total` names, and as many of the `matches` as fit, keeping the
+// `selected` one in view.
+fn draw(
+    tty: &mut impl Write,
+    query: &str,
+    matches: &[(&str, FuzzyMatch)],
+    selected: usize,
+    total: usize,
+) -> std::io::Result<()> {
+    // Some terminals (e.g. a fresh pty) don't know their size.
+    let (width, height) = match terminal::size()? {
+        (0, _) | (_, 0) => FALLBACK_TERMINAL_SIZE,
+        size => size,
+    };
+    let rows = (height as usize).saturating_sub(2).max(1);
+    let first = (selected + 1).saturating_sub(rows);
+
+    queue!(tty, terminal::Clear(ClearType::All))?;
+    queue!(
+        tty,
+        cursor::MoveTo(0, 1),
+        Print(format!("  {}/{}", matches.len(), total))
+    )?;
+    for (row, (index, (name, found))) in matches
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(rows)
+        .enumerate()
+    {
+        queue!(tty, cursor::MoveTo(0, row as u16 + 2))?;
+        if index == selected {
+            queue!(tty, SetAttribute(Attribute::Reverse), Print("> "))?;
+        } else {
+            queue!(tty, Print("  "))?;
+        }
+        for (position, c) in name
+            .chars()
+            .enumerate()
+            .take((width as usize).saturating_sub(2))
+        {
+            if found.positions.contains(&position) {
+                queue!(
+                    tty,
+                    SetAttribute(Attribute::Bold),
+                    Print(c),
+                    SetAttribute(Attribute::NormalIntensity)
+                )?;
+            } else {
+                queue!(tty, Print(c))?;
+            }
+        }
+        queue!(tty, SetAttribute(Attribute::Reset))?;
+    }
+    queue!(tty, cursor::MoveTo(0, 0), Print(format!("> {}", query)))?;
+    tty.flush()
+}
+
+// Aids `pick()`.
+fn pick_impl(names: &[String], query: &str) -> std::io::Result<Option<String>> {
+    let mut tty = std::io::stderr();
+    let _guard = TerminalGuard::new(&mut tty)?;
+    let mut query = query.to_owned();
+    let mut selected: usize = 0;
+    loop {
+        let matches = filter(query.as_str(), names);
+        selected = selected.min(matches.len().saturating_sub(1));
+        draw(&mut tty, query.as_str(), &matches, selected, names.len())?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => return Ok(matches.get(selected).map(|(name, _)| name.to_string())),
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') | KeyCode::Char('g') if control => return Ok(None),
+            KeyCode::Up | KeyCode::BackTab => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') | KeyCode::Char('k') if control => {
+                selected = selected.saturating_sub(1)
+            }
+            KeyCode::Down | KeyCode::Tab => selected += 1,
+            KeyCode::Char('n') | KeyCode::Char('j') if control => selected += 1,
+            KeyCode::Char('u') if control => query.clear(),
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(c) if !control => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => (),
+        }
+    }
+}
+
+// Lets the user pick one of `names` by typing to narrow them down,
+// starting from `query`. Returns `None` if they back out.
+pub fn pick(names: &[String], query: &str) -> Result<Option<String>, RadomskoError> {
+    if !std::io::stderr().is_terminal() {
+        return Err(RadomskoError::BadInput(
+            "picking an entry needs a terminal".to_string(),
+        ));
+    }
+    pick_impl(names, query)
+        .map_err(|e| RadomskoError::IoError(format!("cannot run the picker: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn fuzzy_match_needs_chars_in_order() {
+        let found = fuzzy_match("wag", "web/accounts/github").unwrap();
+        assert_eq!(found.positions, vec![0, 4, 13]);
+        assert!(fuzzy_match("WAG", "web/accounts/github").is_some());
+        assert!(fuzzy_match("gaw", "web/accounts/github").is_none());
+        assert!(fuzzy_match("", "web").unwrap().positions.is_empty());
+    }
+
+    #[test]
+    fn fuzzy_match_prefers_word_starts() {
+        // A greedy match would take the "ac" in "mac".
+        let found = fuzzy_match("ac", "mac/accounts").unwrap();
+        assert_eq!(found.positions, vec![4, 5]);
+    }
+
+    #[test]
+    fn filter_ranks_matches() {
+        let names = names(&[
+            "email/work",
+            "web/accounts/github",
+            "web/accounts/gitlab",
+            "web/github",
+            "web/shop/orange",
+        ]);
+        let ranked: Vec<&str> = filter("github", &names)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(ranked, vec!["web/github", "web/accounts/github"]);
+
+        assert_eq!(filter("", &names).len(), names.len());
+        assert!(filter("xyz", &names).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use std::ops::Range;
+
+use regex::Regex;
+
+use crate::enums::RadomskoError;
+use crate::password_store::highlight;
+
+// Compiles a user-supplied `pattern` into a `Regex`.
+//
+// *    `ignore_case` - matches without regard to case.
+// *    `extended` - treats `pattern` as a regular expression rather
+//          than as a literal string.
+pub fn compile_pattern(
+    pattern: &str,
+    ignore_case: bool,
+    extended: bool,
+) -> Result<Regex, RadomskoError> {
+    let source = match extended {
+        true => pattern.to_owned(),
+        false => regex::escape(pattern),
+    };
+    regex::RegexBuilder::new(source.as_str())
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| RadomskoError::BadInput(e.to_string()))
+}
+
+// Names the part of a compiled glob that matched, which excludes the
+// leading components skipped over when the glob has no `/`.
+const GLOB_GROUP: &str = "glob";
+
+// Compiles a shell-style `glob` into a `Regex` matching whole entry
+// names. `*` and `?` stay within a path component while `**` crosses
+// them. A glob without a `/` need only match the last component, as
+// with `find -name`.
+pub fn compile_glob(glob: &str, ignore_case: bool) -> Result<Regex, RadomskoError> {
+    let mut body = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                body.push_str(".*");
+            }
+            '*' => body.push_str("[^/]*"),
+            '?' => body.push_str("[^/]"),
+            '[' => {
+                body.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    body.push('^');
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('-') => body.push('-'),
+                        Some(c) => body.push_str(regex::escape(c.to_string().as_str()).as_str()),
+                        None => {
+                            return Err(RadomskoError::BadInput(format!(
+                                "unterminated ``['' in glob ``{}''",
+                                glob
+                            )))
+                        }
+                    }
+                }
+                body.push(']');
+            }
+            c => body.push_str(regex::escape(c.to_string().as_str()).as_str()),
+        }
+    }
+    let source = match glob.contains('/') {
+        true => format!("^(?P<{}>{})$", GLOB_GROUP, body),
+        false => format!("^(?:.*/)?(?P<{}>{})$", GLOB_GROUP, body),
+    };
+    regex::RegexBuilder::new(source.as_str())
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| RadomskoError::BadInput(format!("bad glob ``{}'': {}", glob, e)))
+}
+
+// The terms given to `find`, each compiled into a `Regex` that is
+// matched against entry names.
+pub struct SearchTerms {
+    patterns: Vec<Regex>,
+    // Whether every pattern must match, rather than any.
+    require_all: bool,
+}
+
+impl SearchTerms {
+    pub fn new(patterns: Vec<Regex>, require_all: bool) -> SearchTerms {
+        SearchTerms {
+            patterns,
+            require_all,
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self.require_all {
+            true => self.patterns.iter().all(|pattern| pattern.is_match(name)),
+            false => self.patterns.iter().any(|pattern| pattern.is_match(name)),
+        }
+    }
+
+    // Returns the byte ranges of `name` matched by any of the patterns,
+    // sorted and merged where they overlap.
+    pub fn ranges_in(&self, name: &str) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = self
+            .patterns
+            .iter()
+            .flat_map(|pattern| pattern.captures_iter(name))
+            .filter_map(|captures| captures.name(GLOB_GROUP).or(captures.get(0)))
+            .filter(|found| !found.is_empty())
+            .map(|found| found.range())
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+        let mut result: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            match result.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => result.push(range),
+            }
+        }
+        result
+    }
+}
+
+// Returns the lines of `cleartext` matching `pattern`.
+pub fn matching_lines<'a>(cleartext: &'a str, pattern: &Regex) -> Vec<&'a str> {
+    cleartext
+        .lines()
+        .filter(|line| pattern.is_match(line))
+        .collect()
+}
+
+// Returns `line` with every match of `pattern` highlighted.
+pub fn highlight_matches(line: &str, pattern: &Regex) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for found in pattern.find_iter(line) {
+        if found.is_empty() {
+            continue;
+        }
+        result.push_str(&line[last..found.start()]);
+        result.push_str(highlight(found.as_str()).as_str());
+        last = found.end();
+    }
+    result.push_str(&line[last..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    const CLEARTEXT: &str = indoc! {"
+        hunter2
+        username: Klaus
+        url: https://example.com/login?next=a.b
+        email: klaus@example.com
+    "};
+
+    #[test]
+    fn literal_pattern() {
+        let pattern = compile_pattern("example.com", false, false).unwrap();
+        assert_eq!(
+            matching_lines(CLEARTEXT, &pattern),
+            vec![
+                "url: https://example.com/login?next=a.b",
+                "email: klaus@example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn literal_pattern_escapes_metacharacters() {
+        let pattern = compile_pattern("a.b", false, false).unwrap();
+        assert!(pattern.is_match("a.b"));
+        assert!(!pattern.is_match("axb"));
+    }
+
+    #[test]
+    fn case_insensitive_pattern() {
+        let pattern = compile_pattern("klaus", true, false).unwrap();
+        assert_eq!(
+            matching_lines(CLEARTEXT, &pattern),
+            vec!["username: Klaus", "email: klaus@example.com"]
+        );
+    }
+
+    #[test]
+    fn extended_pattern() {
+        let pattern = compile_pattern("^(username|email):", false, true).unwrap();
+        assert_eq!(
+            matching_lines(CLEARTEXT, &pattern),
+            vec!["username: Klaus", "email: klaus@example.com"]
+        );
+    }
+
+    #[test]
+    fn extended_pattern_rejects_bad_regex() {
+        let err = compile_pattern("(unclosed", false, true).unwrap_err();
+        assert!(matches!(err, RadomskoError::BadInput { .. }));
+    }
+
+    #[test]
+    fn glob_matches_last_component_without_slash() {
+        let glob = compile_glob("*.com", false).unwrap();
+        assert!(glob.is_match("web/example.com"));
+        assert!(glob.is_match("example.com"));
+        assert!(!glob.is_match("example.com/login"));
+        assert!(!glob.is_match("web/example.org"));
+    }
+
+    #[test]
+    fn glob_with_slash_matches_whole_name() {
+        let glob = compile_glob("web/*", false).unwrap();
+        assert!(glob.is_match("web/example.com"));
+        assert!(!glob.is_match("web/shop/orange"));
+        assert!(!glob.is_match("old/web/example.com"));
+        assert!(compile_glob("web/**", false)
+            .unwrap()
+            .is_match("web/shop/orange"));
+        assert!(compile_glob("W?B/[!x]*", true)
+            .unwrap()
+            .is_match("web/example.com"));
+        assert!(compile_glob("web/[a-", false).is_err());
+    }
+
+    #[test]
+    fn search_terms_any_or_all() {
+        let terms = vec![
+            compile_pattern("web", false, false).unwrap(),
+            compile_pattern("shop", false, false).unwrap(),
+        ];
+        let any = SearchTerms::new(terms.clone(), false);
+        assert!(any.matches("web/example.com"));
+        assert!(any.matches("shop/orange"));
+        let all = SearchTerms::new(terms, true);
+        assert!(!all.matches("web/example.com"));
+        assert!(all.matches("web/shop/orange"));
+    }
+
+    #[test]
+    fn search_terms_ranges() {
+        let terms = SearchTerms::new(
+            vec![
+                compile_pattern("sho", false, false).unwrap(),
+                compile_pattern("hop", false, false).unwrap(),
+                compile_pattern("e", false, false).unwrap(),
+            ],
+            false,
+        );
+        assert_eq!(terms.ranges_in("web/shop/orange"), vec![1..2, 4..8, 14..15]);
+
+        let glob = SearchTerms::new(vec![compile_glob("*.com", false).unwrap()], false);
+        assert_eq!(glob.ranges_in("web/example.com"), vec![4..15]);
+    }
+
+    #[test]
+    fn highlight_matches_leaves_remainder_untouched() {
+        let pattern = compile_pattern("a", false, false).unwrap();
+        let highlighted = highlight_matches("banana!", &pattern);
+        assert!(highlighted.starts_with('b'));
+        assert!(highlighted.ends_with('!'));
+        assert_eq!(highlighted.matches(highlight("a").as_str()).count(), 3);
+    }
+}
@@ -0,0 +1,482 @@
+// Encrypts and decrypts in-process with Sequoia, reading certificates
+// from a keyring file (e.g. the output of `gpg --export`). Secret keys
+// come from the keyring if it has them (as `gpg --export-secret-keys`
+// writes) and from gpg-agent otherwise.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use sequoia_openpgp as openpgp;
+
+use openpgp::cert::CertParser;
+use openpgp::crypto::{KeyPair, SessionKey};
+use openpgp::packet::key::{SecretParts, UnspecifiedRole};
+use openpgp::packet::{Key, PKESK, SKESK};
+use openpgp::parse::stream::{
+    DecryptionHelper, DecryptorBuilder, MessageStructure, VerificationHelper,
+};
+use openpgp::parse::Parse;
+use openpgp::policy::StandardPolicy;
+use openpgp::serialize::stream::{Encryptor, LiteralWriter, Message};
+use openpgp::types::SymmetricAlgorithm;
+use openpgp::{Cert, KeyHandle};
+
+use crate::crypto::CryptoBackend;
+use crate::enums::{io_error, RadomskoError};
+use crate::gpg_agent::{self, Agent};
+
+// Recovers the `RadomskoError` that `Helper` passed through Sequoia, if
+// any.
+fn crypto_error(err: openpgp::anyhow::Error) -> RadomskoError {
+    match err.downcast::<RadomskoError>() {
+        Ok(err) => err,
+        Err(err) => RadomskoError::CryptoError(err.to_string()),
+    }
+}
+
+pub struct SequoiaBackend {
+    certs: Vec<Cert>,
+    policy: StandardPolicy<'static>,
+    // The socket of gpg-agent, `None` to ask gpgconf for it.
+    agent_socket: Option<PathBuf>,
+}
+
+// Returns whether `cert` is named by `recipient`, which is either a
+// fingerprint or key ID of any of its keys, one of its user IDs in
+// full, or the email address of one (bare or as `<address>`). Part of
+// a user ID doesn't do, lest `bob@example.com` name
+// `jimbob@example.com` as well.
+fn cert_matches(cert: &Cert, recipient: &str) -> bool {
+    let hex = recipient.trim_start_matches("0x");
+    if let Ok(handle) = hex.parse::<KeyHandle>() {
+        if cert
+            .keys()
+            .any(|key| key.key().key_handle().aliases(&handle))
+        {
+            return true;
+        }
+    }
+    let address = recipient
+        .strip_prefix('<')
+        .and_then(|address| address.strip_suffix('>'))
+        .unwrap_or(recipient)
+        .to_lowercase();
+    cert.userids().any(|userid| {
+        let userid = userid.userid();
+        userid.value() == recipient.as_bytes()
+            || userid
+                .email()
+                .ok()
+                .flatten()
+                .is_some_and(|email| email.to_lowercase() == address)
+    })
+}
+
+// Unlocks the secret part of `key`, prompting for its passphrase if it
+// has one.
+fn keypair_for(key: &Key<SecretParts, UnspecifiedRole>) -> Result<KeyPair, RadomskoError> {
+    let mut key = key.clone();
+    if key.secret().is_encrypted() {
+        let passphrase =
+            rpassword::prompt_password(format!("Enter passphrase for key {}: ", key.fingerprint()))
+                .map_err(io_error("read the passphrase"))?;
+        key = key
+            .decrypt_secret(&passphrase.into())
+            .map_err(crypto_error)?;
+    }
+    key.into_keypair().map_err(crypto_error)
+}
+
+struct Helper<'a> {
+    backend: &'a SequoiaBackend,
+    // Connected to once a key without a secret in the keyring is
+    // needed.
+    agent: Option<Agent>,
+}
+
+impl Helper<'_> {
+    fn agent(&mut self) -> Result<&mut Agent, RadomskoError> {
+        if self.agent.is_none() {
+            let socket = match &self.backend.agent_socket {
+                Some(socket) => socket.clone(),
+                None => gpg_agent::socket()?,
+            };
+            self.agent = Some(Agent::connect(&socket)?);
+        }
+        Ok(self.agent.as_mut().unwrap())
+    }
+}
+
+impl VerificationHelper for Helper<'_> {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> openpgp::Result<Vec<Cert>> {
+        Ok(Vec::new())
+    }
+
+    // Passwords aren't signed, so there is nothing to check.
+    fn check(&mut self, _structure: MessageStructure) -> openpgp::Result<()> {
+        Ok(())
+    }
+}
+
+impl DecryptionHelper for Helper<'_> {
+    fn decrypt(
+        &mut self,
+        pkesks: &[PKESK],
+        _skesks: &[SKESK],
+        sym_algo: Option<SymmetricAlgorithm>,
+        decrypt: &mut dyn FnMut(Option<SymmetricAlgorithm>, &SessionKey) -> bool,
+    ) -> openpgp::Result<Option<Cert>> {
+        let backend = self.backend;
+        // Why a key that might have decrypted could not be tried, which
+        // says more than that there was no secret key.
+        let mut unsupported: Option<RadomskoError> = None;
+        for pkesk in pkesks {
+            for cert in backend.certs.iter() {
+                let keys = cert
+                    .keys()
+                    .with_policy(&backend.policy, None)
+                    .supported()
+                    .for_storage_encryption()
+                    .for_transport_encryption();
+                for key in keys {
+                    if let Some(recipient) = pkesk.recipient() {
+                        if !key.key().key_handle().aliases(&recipient) {
+                            continue;
+                        }
+                    }
+                    let decrypted = match key.key().parts_as_secret() {
+                        Ok(secret) => {
+                            let mut keypair = keypair_for(secret.role_as_unspecified())?;
+                            pkesk.decrypt(&mut keypair, sym_algo)
+                        }
+                        Err(_) => {
+                            let key = key.key().role_as_unspecified();
+                            let keygrip = match gpg_agent::keygrip(key) {
+                                Ok(keygrip) => keygrip,
+                                Err(err) => {
+                                    unsupported = Some(err);
+                                    continue;
+                                }
+                            };
+                            let mut keypair = match self.agent()?.keypair_for(key, keygrip)? {
+                                Some(keypair) => keypair,
+                                None => continue,
+                            };
+                            let decrypted = pkesk.decrypt(&mut keypair, sym_algo);
+                            // Cancelling the pinentry, say, must not
+                            // go on to the next key.
+                            if let Some(failure) = keypair.failure.take() {
+                                return Err(failure.into());
+                            }
+                            decrypted
+                        }
+                    };
+                    if decrypted
+                        .map(|(algo, session_key)| decrypt(algo, &session_key))
+                        .unwrap_or(false)
+                    {
+                        return Ok(Some(cert.clone()));
+                    }
+                }
+            }
+        }
+        match unsupported {
+            Some(err) => Err(err.into()),
+            None => Err(openpgp::anyhow::anyhow!(
+                "no secret key in the keyring or gpg-agent"
+            )),
+        }
+    }
+}
+
+impl SequoiaBackend {
+    pub fn new(keyring: &Path) -> Result<SequoiaBackend, RadomskoError> {
+        let certs = CertParser::from_file(keyring)
+            .map_err(crypto_error)?
+            .collect::<openpgp::Result<Vec<Cert>>>()
+            .map_err(crypto_error)?;
+        Ok(SequoiaBackend {
+            certs,
+            policy: StandardPolicy::new(),
+            agent_socket: None,
+        })
+    }
+}
+
+impl CryptoBackend for SequoiaBackend {
+    fn decrypt(&self, password: &Path) -> Result<String, RadomskoError> {
+        let mut decryptor = DecryptorBuilder::from_file(password)
+            .map_err(crypto_error)?
+            .with_policy(
+                &self.policy,
+                None,
+                Helper {
+                    backend: self,
+                    agent: None,
+                },
+            )
+            .map_err(crypto_error)?;
+        let mut cleartext = Vec::new();
+        decryptor
+            .read_to_end(&mut cleartext)
+            .map_err(|e| RadomskoError::CryptoError(e.to_string()))?;
+        String::from_utf8(cleartext)
+            .map_err(|_| RadomskoError::BadInput("password is not UTF-8".to_string()))
+    }
+
+    fn encrypt(&self, cleartext: &str, recipients: &[String]) -> Result<Vec<u8>, RadomskoError> {
+        assert!(!recipients.is_empty());
+        let mut keys = Vec::new();
+        for recipient in recipients {
+            let before = keys.len();
+            let certs: Vec<&Cert> = self
+                .certs
+                .iter()
+                .filter(|cert| cert_matches(cert, recipient))
+                .collect();
+            if certs.len() > 1 {
+                return Err(RadomskoError::InvalidRecipient(
+                    recipient.clone(),
+                    "matches more than one certificate in the keyring".to_string(),
+                ));
+            }
+            for cert in certs {
+                keys.extend(
+                    cert.keys()
+                        .with_policy(&self.policy, None)
+                        .supported()
+                        .alive()
+                        .revoked(false)
+                        .for_storage_encryption()
+                        .for_transport_encryption(),
+                );
+            }
+            if keys.len() == before {
+                return Err(RadomskoError::NoRecipients(format!(
+                    "no usable key for ``{}'' in the keyring",
+                    recipient
+                )));
+            }
+        }
+
+        let mut encrypted = Vec::new();
+        let message = Message::new(&mut encrypted);
+        let message = Encryptor::for_recipients(message, keys)
+            .build()
+            .map_err(crypto_error)?;
+        let mut message = LiteralWriter::new(message).build().map_err(crypto_error)?;
+        message
+            .write_all(cleartext.as_bytes())
+            .map_err(|e| RadomskoError::CryptoError(e.to_string()))?;
+        message.finalize().map_err(crypto_error)?;
+        Ok(encrypted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openpgp::cert::CertBuilder;
+    use openpgp::serialize::Serialize;
+    use std::os::unix::fs::DirBuilderExt;
+
+    // Generates throwaway keys for each of `userids` and returns a
+    // backend using a keyring holding all of them.
+    fn scratch_backend(userids: &[&str]) -> (tempfile::TempDir, SequoiaBackend, Vec<Cert>) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let keyring = tmp_dir.path().join("keyring.pgp");
+        let mut file = std::fs::File::create(&keyring).unwrap();
+        let mut certs = Vec::new();
+        for userid in userids {
+            let (cert, _) = CertBuilder::general_purpose(Some(*userid))
+                .generate()
+                .unwrap();
+            cert.as_tsk().serialize(&mut file).unwrap();
+            certs.push(cert);
+        }
+        let backend = SequoiaBackend::new(&keyring).unwrap();
+        (tmp_dir, backend, certs)
+    }
+
+    fn encrypt_to_file(
+        backend: &SequoiaBackend,
+        directory: &Path,
+        cleartext: &str,
+        recipients: &[&str],
+    ) -> std::path::PathBuf {
+        let recipients: Vec<String> = recipients.iter().map(|r| r.to_string()).collect();
+        let password = directory.join("password.gpg");
+        std::fs::write(&password, backend.encrypt(cleartext, &recipients).unwrap()).unwrap();
+        password
+    }
+
+    #[test]
+    fn round_trip() {
+        let (tmp_dir, backend, _) = scratch_backend(&["Alice <alice@example.com>"]);
+        let password = encrypt_to_file(
+            &backend,
+            tmp_dir.path(),
+            "hunter2\nusername: alice\n",
+            &["alice@example.com"],
+        );
+        assert_eq!(
+            backend.decrypt(&password).unwrap(),
+            "hunter2\nusername: alice\n"
+        );
+    }
+
+    #[test]
+    fn recipients_by_fingerprint() {
+        let (tmp_dir, backend, certs) = scratch_backend(&["alice@example.com", "bob@example.com"]);
+        let fingerprint = format!("0x{}", certs[1].fingerprint().to_hex());
+        let password = encrypt_to_file(&backend, tmp_dir.path(), "hunter2", &[&fingerprint]);
+        assert_eq!(backend.decrypt(&password).unwrap(), "hunter2");
+    }
+
+    #[test]
+    fn recipients_by_exact_address() {
+        let (tmp_dir, backend, certs) =
+            scratch_backend(&["Bob <bob@example.com>", "Jim Bob <jimbob@example.com>"]);
+        for (recipient, cert) in [
+            ("bob@example.com", &certs[0]),
+            ("<BOB@example.com>", &certs[0]),
+            ("Jim Bob <jimbob@example.com>", &certs[1]),
+        ] {
+            let password = encrypt_to_file(&backend, tmp_dir.path(), "hunter2", &[recipient]);
+            let pile = openpgp::PacketPile::from_file(&password).unwrap();
+            let pkesks: Vec<&PKESK> = pile
+                .children()
+                .filter_map(|packet| match packet {
+                    openpgp::Packet::PKESK(pkesk) => Some(pkesk),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(pkesks.len(), 1, "{}", recipient);
+            let handle = pkesks[0].recipient().unwrap();
+            assert!(
+                cert.keys()
+                    .any(|key| key.key().key_handle().aliases(&handle)),
+                "{}",
+                recipient
+            );
+        }
+
+        let err = backend
+            .encrypt("hunter2", &["example.com".to_string()])
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::NoRecipients { .. }));
+    }
+
+    #[test]
+    fn encrypt_rejects_ambiguous_recipient() {
+        let (_tmp_dir, backend, _) =
+            scratch_backend(&["Bob <bob@example.com>", "Robert <bob@example.com>"]);
+        let err = backend
+            .encrypt("hunter2", &["bob@example.com".to_string()])
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::InvalidRecipient { .. }));
+    }
+
+    #[test]
+    fn decrypt_reports_unsupported_agent_keys() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (cert, _) = CertBuilder::general_purpose(Some("alice@example.com"))
+            .set_cipher_suite(openpgp::cert::CipherSuite::P256)
+            .generate()
+            .unwrap();
+        // Without its secret keys, so that they would have to come from
+        // gpg-agent, which is never reached.
+        let keyring = tmp_dir.path().join("keyring.pgp");
+        cert.serialize(&mut std::fs::File::create(&keyring).unwrap())
+            .unwrap();
+        let backend = SequoiaBackend {
+            agent_socket: Some(tmp_dir.path().join("no-agent")),
+            ..SequoiaBackend::new(&keyring).unwrap()
+        };
+        let password = encrypt_to_file(&backend, tmp_dir.path(), "hunter2", &["alice@example.com"]);
+        let err = backend.decrypt(&password).unwrap_err();
+        assert!(matches!(err, RadomskoError::UnsupportedKey { .. }));
+    }
+
+    #[test]
+    fn encrypt_rejects_unknown_recipient() {
+        let (_tmp_dir, backend, _) = scratch_backend(&["alice@example.com"]);
+        let err = backend
+            .encrypt("hunter2", &["mallory@example.com".to_string()])
+            .unwrap_err();
+        assert!(matches!(err, RadomskoError::NoRecipients { .. }));
+    }
+
+    // Runs `program` (gpg or gpgconf) with `gnupghome` as its home
+    // directory and returns its output.
+    fn gnupg(program: &str, gnupghome: &Path, args: &[&str], input: &str) -> String {
+        let capture_data = subprocess::Exec::cmd(program)
+            .args(args)
+            .env("GNUPGHOME", gnupghome)
+            .stdin(input)
+            .stdout(subprocess::Redirection::Pipe)
+            .stderr(subprocess::NullFile)
+            .capture()
+            .unwrap();
+        assert!(capture_data.success(), "{} {:?}", program, args);
+        capture_data.stdout_str()
+    }
+
+    #[test]
+    fn decrypt_with_gpg_agent() {
+        for key_parameters in [
+            "Key-Type: rsa\nKey-Length: 2048\nSubkey-Type: rsa\nSubkey-Length: 2048\n",
+            "Key-Type: eddsa\nKey-Curve: ed25519\nSubkey-Type: ecdh\nSubkey-Curve: cv25519\n",
+        ] {
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let gnupghome = tmp_dir.path().join("gnupg");
+            std::fs::DirBuilder::new()
+                .mode(0o700)
+                .create(&gnupghome)
+                .unwrap();
+            gnupg(
+                "gpg",
+                &gnupghome,
+                &["--batch", "--gen-key"],
+                format!(
+                    "%no-protection\n{}Name-Email: alice@example.com\nExpire-Date: 0\n%commit\n",
+                    key_parameters
+                )
+                .as_str(),
+            );
+            // Only the public keys go into the keyring.
+            let keyring = tmp_dir.path().join("keyring.pgp");
+            gnupg(
+                "gpg",
+                &gnupghome,
+                &["--batch", "--export", "--output", keyring.to_str().unwrap()],
+                "",
+            );
+            gnupg("gpgconf", &gnupghome, &["--launch", "gpg-agent"], "");
+            let socket = gnupg("gpgconf", &gnupghome, &["--list-dirs", "agent-socket"], "");
+
+            let backend = SequoiaBackend {
+                agent_socket: Some(PathBuf::from(socket.trim_end())),
+                ..SequoiaBackend::new(&keyring).unwrap()
+            };
+            let password = encrypt_to_file(
+                &backend,
+                tmp_dir.path(),
+                "hunter2\nusername: alice\n",
+                &["alice@example.com"],
+            );
+            let decrypted = backend.decrypt(&password);
+            gnupg("gpgconf", &gnupghome, &["--kill", "gpg-agent"], "");
+            assert_eq!(decrypted.unwrap(), "hunter2\nusername: alice\n");
+        }
+    }
+
+    #[test]
+    fn decrypt_needs_secret_key() {
+        let (tmp_dir, alice, _) = scratch_backend(&["alice@example.com"]);
+        let (_bob_tmp_dir, bob, _) = scratch_backend(&["bob@example.com"]);
+        let password = encrypt_to_file(&alice, tmp_dir.path(), "hunter2", &["alice@example.com"]);
+        let err = bob.decrypt(&password).unwrap_err();
+        assert!(matches!(err, RadomskoError::CryptoError { .. }));
+    }
+}